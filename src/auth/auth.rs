@@ -1,107 +1,388 @@
+use crate::config::config::{AudioAttachment, Config, DropboxAuthMode};
+use crate::dashboard::dashboard::escape_html;
+use crate::error::error::SecretaryError;
+use crate::http::http::HttpClient;
 use crate::secretary::Secretary;
-use oauth2::basic::BasicClient;
+use oauth2::basic::{BasicClient, BasicTokenResponse};
 use oauth2::reqwest::async_http_client;
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge,
+    RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
 use reqwest::StatusCode;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
 use warp::Filter;
 use webbrowser;
 
-pub async fn initialize(secretary: &mut Secretary) -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize OAuth2 client
-    let client = Arc::new(
-        BasicClient::new(
-            ClientId::new(secretary.config.dropbox_client_id.clone()),
-            Some(ClientSecret::new(
-                secretary.config.dropbox_client_secret.clone(),
-            )),
-            AuthUrl::new("https://www.dropbox.com/oauth2/authorize".to_string())?,
-            Some(TokenUrl::new(
-                "https://api.dropboxapi.com/oauth2/token".to_string(),
-            )?),
-        )
-        .set_redirect_uri(RedirectUrl::new(
-            "http://localhost:8080/callback".to_string(),
-        )?),
-    );
+type CallbackSender = Arc<Mutex<Option<oneshot::Sender<Result<String, String>>>>>;
+
+/// Slot through which an authorization code can be handed to a waiting headless
+/// flow from outside, e.g. the dashboard's `/auth/code` endpoint.
+pub type PendingAuthCode = Arc<Mutex<Option<oneshot::Sender<String>>>>;
 
+pub async fn initialize(secretary: &mut Secretary) -> Result<(), SecretaryError> {
     // Check if we need to run OAuth2 flow
-    if !is_token_valid(&secretary.dropbox_client.access_token).await {
-        // Create oneshot channel to wait for the authorization code
-        let (tx, rx) = oneshot::channel();
-        let tx = Arc::new(Mutex::new(Some(tx)));
-
-        // Clone the client and oneshot sender for the server
-        let client_clone = Arc::clone(&client);
-        let tx_clone = Arc::clone(&tx);
-
-        // Run the server to handle OAuth callback
-        tokio::spawn(async move {
-            run_server(client_clone, tx_clone).await;
-        });
-
-        // Generate the authorization URL and open it in the browser
-        let (auth_url, _csrf_token) = client
-            .authorize_url(CsrfToken::new_random)
-            .add_scope(Scope::new("files.content.read".to_string()))
-            .url();
-
-        println!("Opening browser to: {}", auth_url);
-        webbrowser::open(auth_url.as_str())?;
-
-        // Wait for the authorization code
-        let auth_code = rx.await?;
-        println!("Authorization code received: {}", auth_code);
-
-        // Exchange the authorization code for an access token
-        let token = client
-            .exchange_code(AuthorizationCode::new(auth_code))
-            .request_async(async_http_client)
-            .await?;
-
-        println!("Access token: {}", token.access_token().secret());
-
-        // Update the Dropbox client with the access token
-        secretary.dropbox_client.access_token = token.access_token().secret().clone();
-    } else {
+    if is_token_valid(
+        &secretary.dropbox_client.http,
+        &secretary.dropbox_client.access_token(),
+    )
+    .await
+    {
         println!("Using existing access token.");
+    } else if !secretary.dropbox_client.refresh_token.is_empty()
+        && refresh_and_persist(secretary).await.is_ok()
+    {
+        println!("Using refreshed access token.");
+    } else {
+        let auth_mode = if secretary.config.non_interactive {
+            DropboxAuthMode::NonInteractive
+        } else {
+            secretary.config.dropbox.auth_mode
+        };
+        let token = match auth_mode {
+            DropboxAuthMode::Browser => authorize_in_browser(&secretary.config).await?,
+            DropboxAuthMode::Headless => {
+                authorize_headless(&secretary.config, &secretary.pending_auth_code).await?
+            }
+            DropboxAuthMode::NonInteractive => {
+                return Err(SecretaryError::Auth(
+                    "Dropbox authorization is required, but Secretary is running \
+                    non-interactively. Authorize once in 'browser' or 'headless' mode to store a \
+                    refresh token."
+                        .to_string(),
+                ))
+            }
+        };
+        println!("Access token received.");
+
+        // Update the Dropbox client and persist the tokens for the next run
+        let access_token = token.access_token().secret().clone();
+        secretary
+            .dropbox_client
+            .set_access_token(access_token.clone());
+        secretary.config.dropbox.access_token.set(access_token);
+        if let Some(refresh_token) = token.refresh_token() {
+            secretary.dropbox_client.refresh_token = refresh_token.secret().clone();
+            secretary
+                .config
+                .dropbox
+                .refresh_token
+                .set(refresh_token.secret().clone());
+        }
+        secretary.config.save()?;
     }
 
     Ok(())
 }
 
-async fn run_server(client: Arc<BasicClient>, tx: Arc<Mutex<Option<oneshot::Sender<String>>>>) {
-    let routes = warp::path("callback")
+/// Builds the Dropbox OAuth2 client. The client secret is optional: with PKCE,
+/// Dropbox only needs the client id. Without a redirect URI, Dropbox shows the
+/// authorization code to the user instead of redirecting.
+fn oauth_client(config: &Config, with_redirect: bool) -> Result<BasicClient, SecretaryError> {
+    let client_secret = if config.dropbox.client_secret.is_empty() {
+        None
+    } else {
+        Some(ClientSecret::new(
+            config.dropbox.client_secret.expose().to_string(),
+        ))
+    };
+
+    let invalid_url = |error| SecretaryError::Config(format!("invalid OAuth URL: {}", error));
+    let client = BasicClient::new(
+        ClientId::new(config.dropbox.client_id.clone()),
+        client_secret,
+        AuthUrl::new("https://www.dropbox.com/oauth2/authorize".to_string())
+            .map_err(invalid_url)?,
+        Some(
+            TokenUrl::new("https://api.dropboxapi.com/oauth2/token".to_string())
+                .map_err(invalid_url)?,
+        ),
+    )
+    .set_auth_type(AuthType::RequestBody);
+
+    if !with_redirect {
+        return Ok(client);
+    }
+    Ok(client.set_redirect_uri(
+        RedirectUrl::new(format!(
+            "http://{}:{}/callback",
+            config.dropbox.callback_host, config.dropbox.callback_port
+        ))
+        .map_err(invalid_url)?,
+    ))
+}
+
+/// The Dropbox permissions Secretary needs with the current config.
+fn scopes(config: &Config) -> Vec<Scope> {
+    let mut scopes = vec!["files.content.read"];
+    if config.obsidian.audio_attachment == AudioAttachment::SharedLink {
+        scopes.extend(["sharing.read", "sharing.write"]);
+    }
+    scopes
+        .into_iter()
+        .map(|scope| Scope::new(scope.to_string()))
+        .collect()
+}
+
+/// Runs the authorization code flow with PKCE: opens the authorization page, waits
+/// for the callback carrying a matching `state`, then exchanges the code.
+async fn authorize_in_browser(config: &Config) -> Result<BasicTokenResponse, SecretaryError> {
+    let client = oauth_client(config, true)?;
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    // Offline access makes Dropbox issue a long-lived refresh token alongside the
+    // access token.
+    let (auth_url, csrf_token) = client
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(scopes(config))
+        .add_extra_param("token_access_type", "offline")
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    // Run the server to handle the OAuth callback until a code arrives
+    let (tx, rx) = oneshot::channel();
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let address = callback_address(config).await?;
+    let (bound_address, server) = warp::serve(callback_routes(
+        csrf_token.secret().clone(),
+        Arc::new(Mutex::new(Some(tx))),
+    ))
+    .try_bind_with_graceful_shutdown(address, async {
+        shutdown_rx.await.ok();
+    })
+    .map_err(|error| {
+        SecretaryError::Auth(format!(
+            "could not start the callback server on {}: {}",
+            address, error
+        ))
+    })?;
+    let server = tokio::spawn(server);
+    println!(
+        "Waiting for the Dropbox callback on http://{}/callback",
+        bound_address
+    );
+
+    println!("Opening browser to: {}", auth_url);
+    webbrowser::open(auth_url.as_str())
+        .map_err(|error| SecretaryError::Auth(format!("could not open a browser: {}", error)))?;
+
+    // Wait for the authorization code, then stop the callback server either way
+    let callback = timeout(Duration::from_secs(config.dropbox.auth_timeout_secs), rx).await;
+    let _ = shutdown_tx.send(());
+    let _ = server.await;
+
+    let auth_code = match callback {
+        Ok(Ok(Ok(code))) => code,
+        Ok(Ok(Err(error))) => return Err(SecretaryError::Auth(error)),
+        Ok(Err(_)) => {
+            return Err(SecretaryError::Auth(
+                "callback server stopped before receiving a code".to_string(),
+            ))
+        }
+        Err(_) => {
+            return Err(SecretaryError::Auth(format!(
+                "timed out after {}s waiting for Dropbox authorization",
+                config.dropbox.auth_timeout_secs
+            )))
+        }
+    };
+    println!("Authorization code received.");
+
+    // Exchange the authorization code for an access token
+    client
+        .exchange_code(AuthorizationCode::new(auth_code))
+        .set_pkce_verifier(pkce_verifier)
+        .request_async(async_http_client)
+        .await
+        .map_err(|error| {
+            SecretaryError::Auth(format!(
+                "failed to exchange the authorization code: {}",
+                error
+            ))
+        })
+}
+
+/// Runs the authorization code flow with PKCE and no redirect: prints the
+/// authorization URL and waits for the code Dropbox displays, pasted on stdin or
+/// posted to the dashboard.
+async fn authorize_headless(
+    config: &Config,
+    pending_auth_code: &PendingAuthCode,
+) -> Result<BasicTokenResponse, SecretaryError> {
+    let client = oauth_client(config, false)?;
+    let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+    let (auth_url, _csrf_token) = client
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(scopes(config))
+        .add_extra_param("token_access_type", "offline")
+        .set_pkce_challenge(pkce_challenge)
+        .url();
+
+    let (tx, rx) = oneshot::channel();
+    *pending_auth_code.lock().unwrap() = Some(tx);
+
+    println!("Open this URL in any browser and authorize Secretary:");
+    println!("{}", auth_url);
+    println!(
+        "Then paste the code shown by Dropbox here, or POST it as 'code' to http://localhost:{}/auth/code",
+        config.dashboard.port
+    );
+
+    // stdin may be closed (e.g. under systemd); the dashboard endpoint still works then
+    let from_stdin = async {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) if !line.trim().is_empty() => return line.trim().to_string(),
+                Ok(Some(_)) => continue,
+                _ => std::future::pending::<()>().await,
+            }
+        }
+    };
+    // Whichever way the code arrives, the reader is dropped with the other branch
+    let received = async {
+        tokio::select! {
+            code = from_stdin => Ok(code),
+            code = rx => code,
+        }
+    };
+
+    let received = timeout(
+        Duration::from_secs(config.dropbox.auth_timeout_secs),
+        received,
+    )
+    .await;
+    pending_auth_code.lock().unwrap().take();
+    let auth_code = match received {
+        Ok(Ok(code)) => code,
+        Ok(Err(_)) => {
+            return Err(SecretaryError::Auth(
+                "authorization code channel closed".to_string(),
+            ))
+        }
+        Err(_) => {
+            return Err(SecretaryError::Auth(format!(
+                "timed out after {}s waiting for the Dropbox authorization code",
+                config.dropbox.auth_timeout_secs
+            )));
+        }
+    };
+    println!("Authorization code received.");
+
+    client
+        .exchange_code(AuthorizationCode::new(auth_code))
+        .set_pkce_verifier(pkce_verifier)
+        .request_async(async_http_client)
+        .await
+        .map_err(|error| {
+            SecretaryError::Auth(format!(
+                "failed to exchange the authorization code: {}",
+                error
+            ))
+        })
+}
+
+async fn callback_address(config: &Config) -> Result<SocketAddr, SecretaryError> {
+    tokio::net::lookup_host((
+        config.dropbox.callback_host.as_str(),
+        config.dropbox.callback_port,
+    ))
+    .await
+    .ok()
+    .and_then(|mut addresses| addresses.next())
+    .ok_or_else(|| {
+        SecretaryError::Config(format!(
+            "could not resolve callback host '{}'",
+            config.dropbox.callback_host
+        ))
+    })
+}
+
+/// Refreshes the access token with the stored refresh token and writes it back to the config.
+async fn refresh_and_persist(secretary: &mut Secretary) -> Result<(), SecretaryError> {
+    secretary.dropbox_client.refresh_access_token().await?;
+    secretary
+        .config
+        .dropbox
+        .access_token
+        .set(secretary.dropbox_client.access_token());
+    secretary.config.save()
+}
+
+fn callback_routes(
+    expected_state: String,
+    tx: CallbackSender,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::path("callback")
         .and(warp::query::<HashMap<String, String>>())
         .map(move |params: HashMap<String, String>| {
-            if let Some(code) = params.get("code") {
-                println!("Received code: {}", code);
+            // Ignore callbacks that were not started by this flow
+            if params.get("state") != Some(&expected_state) {
+                return warp::reply::with_status(
+                    callback_page("Invalid authorization state. Please restart the authorization."),
+                    warp::http::StatusCode::BAD_REQUEST,
+                );
+            }
+
+            if let Some(error) = params.get("error") {
+                let description = params.get("error_description").cloned().unwrap_or_default();
                 if let Some(tx) = tx.lock().unwrap().take() {
-                    tx.send(code.clone()).expect("Failed to send auth code");
+                    let _ = tx.send(Err(format!("{} {}", error, description).trim().to_string()));
                 }
+                let message = if error == "access_denied" {
+                    "Access to Dropbox was denied. Secretary cannot run without it.".to_string()
+                } else {
+                    format!("Dropbox returned an error: {}", error)
+                };
+                return warp::reply::with_status(
+                    callback_page(&message),
+                    warp::http::StatusCode::FORBIDDEN,
+                );
             }
-            warp::reply::html("Authorization code received. You can close this window.")
-        });
 
-    println!("Server running on http://localhost:8080/callback");
-    warp::serve(routes).run(([127, 0, 0, 1], 8080)).await;
+            match params.get("code") {
+                Some(code) => {
+                    if let Some(tx) = tx.lock().unwrap().take() {
+                        let _ = tx.send(Ok(code.clone()));
+                    }
+                    warp::reply::with_status(
+                        callback_page("Authorization code received. You can close this window."),
+                        warp::http::StatusCode::OK,
+                    )
+                }
+                None => warp::reply::with_status(
+                    callback_page("Missing authorization code."),
+                    warp::http::StatusCode::BAD_REQUEST,
+                ),
+            }
+        })
+}
+
+/// A page showing `message`, which may include text from the query string.
+fn callback_page(message: &str) -> warp::reply::Html<String> {
+    warp::reply::html(format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Secretary</title></head><body><p>{}</p></body></html>",
+        escape_html(message)
+    ))
 }
 
 // Function to check if the token is valid
-async fn is_token_valid(token: &str) -> bool {
+async fn is_token_valid(http: &HttpClient, token: &str) -> bool {
     if token.is_empty() {
         return false;
     }
-    let client = reqwest::Client::new();
-    let res = client
-        .post("https://api.dropboxapi.com/2/check/user")
-        .bearer_auth(token)
-        .send()
+    let res = http
+        .send(|| {
+            http.client
+                .post("https://api.dropboxapi.com/2/check/user")
+                .bearer_auth(token)
+        })
         .await;
 
     match res {
@@ -0,0 +1,150 @@
+use crate::config::config::{CacheRetention, StorageConfig};
+use crate::error::error::SecretaryError;
+use crate::ledger::ledger::Ledger;
+use crate::models::dropbox_file_metadata::DropboxFileMetadata;
+use crate::models::recording_status::RecordingStatus;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The directory of downloaded recordings and its retention policy.
+pub struct AudioCache {
+    dir: PathBuf,
+    retention: CacheRetention,
+    keep_for: Duration,
+    max_size_bytes: u64,
+}
+
+/// What a cleanup removed.
+#[derive(Debug, Default)]
+pub struct CleanReport {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+struct CachedFile {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl AudioCache {
+    pub fn new(config: &StorageConfig) -> Self {
+        AudioCache {
+            dir: PathBuf::from(&config.cache_dir),
+            retention: config.cache_retention,
+            keep_for: Duration::from_secs(config.cache_keep_days * 24 * 60 * 60),
+            max_size_bytes: config.cache_max_size_mb * 1024 * 1024,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where a recording is kept: named after its Dropbox id, which survives
+    /// renames, with the original extension.
+    pub fn path_for(&self, metadata: &DropboxFileMetadata) -> PathBuf {
+        let id: String = metadata
+            .id
+            .trim_start_matches("id:")
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        match Path::new(&metadata.name).extension() {
+            Some(extension) => self
+                .dir
+                .join(format!("{}.{}", id, extension.to_string_lossy())),
+            None => self.dir.join(id),
+        }
+    }
+
+    /// Removes the cached recording of a saved note, if the policy says so.
+    pub fn release(&self, path: &Path) -> Result<(), SecretaryError> {
+        if self.retention == CacheRetention::DeleteAfterSave && path.starts_with(&self.dir) {
+            match fs::remove_file(path) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(error.into())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the retention policy to the whole cache directory. Recordings the
+    /// ledger shows as still in the pipeline are kept; with `all`, everything
+    /// else is removed regardless of the policy.
+    pub fn clean(&self, ledger: &Ledger, all: bool) -> Result<CleanReport, SecretaryError> {
+        let mut report = CleanReport::default();
+        if !self.dir.is_dir() {
+            return Ok(report);
+        }
+
+        let in_progress: HashSet<PathBuf> = ledger
+            .entries()
+            .iter()
+            .filter(|entry| {
+                !matches!(
+                    entry.status,
+                    RecordingStatus::Saved | RecordingStatus::Failed(_)
+                )
+            })
+            .map(|entry| self.path_for(&entry.audio_file_metadata))
+            .collect();
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            // Partial downloads belong to the recording they will become
+            let final_path = path.with_extension("");
+            let is_part = path
+                .extension()
+                .is_some_and(|extension| extension == "part");
+            if in_progress.contains(&path) || (is_part && in_progress.contains(&final_path)) {
+                continue;
+            }
+            files.push(CachedFile {
+                path,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+
+        let now = SystemTime::now();
+        let expired: Vec<CachedFile> = match (all, self.retention) {
+            (true, _) | (false, CacheRetention::DeleteAfterSave) => files,
+            (false, CacheRetention::KeepDays) => files
+                .into_iter()
+                .filter(|file| {
+                    now.duration_since(file.modified).unwrap_or_default() > self.keep_for
+                })
+                .collect(),
+            (false, CacheRetention::MaxSize) => {
+                files.sort_by_key(|file| file.modified);
+                let mut total: u64 = files.iter().map(|file| file.size).sum();
+                files
+                    .into_iter()
+                    .take_while(|file| {
+                        let over = total > self.max_size_bytes;
+                        total = total.saturating_sub(file.size);
+                        over
+                    })
+                    .collect()
+            }
+        };
+
+        for file in expired {
+            fs::remove_file(&file.path)?;
+            report.files += 1;
+            report.bytes += file.size;
+        }
+        Ok(report)
+    }
+}
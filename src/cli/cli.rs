@@ -0,0 +1,89 @@
+use crate::config::config::Config;
+use crate::error::error::SecretaryError;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "Usage: secretary [--config <path>] [--<config-key> <value>]... [command]
+
+Options:
+  --config <path>        Config file to load (default: config.toml)
+  --<config-key> <value> Override any config key, e.g. --obsidian-vault-path ~/Vault
+  --non-interactive      Never prompt; fail when required keys are missing
+  --dry-run              List what would be processed, or with reprocess show the
+                         changes to the note, without writing anything
+  --allow-paid-calls     Let reprocess --dry-run download, transcribe and analyze
+                         the recording, which OpenAI charges for
+  --help                 Show this message
+
+Commands:
+  secrets set <name>     Store a value in the encrypted secrets file
+  secrets list           List the names stored in the secrets file
+  secrets migrate        Move plaintext secrets from the config into the secrets file
+  reprocess <recording> [profile]
+                         Regenerate a recording's note (by Dropbox id or file name)
+  usage [day|month]      Show audio minutes, tokens and cost per day or month
+  cache clean [all]      Apply the cache retention policy, or remove all cached recordings";
+
+/// Command line arguments: the config file, config key overrides and the
+/// remaining positional command words.
+#[derive(Debug, Default)]
+pub struct Cli {
+    pub config_path: PathBuf,
+    pub overrides: Vec<(String, String)>,
+    pub command: Vec<String>,
+    pub help: bool,
+    /// Whether a dry run may make paid API calls, which reprocessing needs.
+    pub allow_paid_calls: bool,
+}
+
+impl Cli {
+    pub fn from_env() -> Result<Cli, SecretaryError> {
+        Cli::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, SecretaryError> {
+        let boolean_keys = Config::boolean_keys();
+        let mut cli = Cli {
+            config_path: PathBuf::from("config.toml"),
+            ..Cli::default()
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                cli.command.push(arg);
+                continue;
+            };
+
+            let (name, inline_value) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let key = name.replace('-', "_");
+
+            if key == "help" {
+                cli.help = true;
+                continue;
+            }
+            if key == "allow_paid_calls" {
+                cli.allow_paid_calls = true;
+                continue;
+            }
+
+            let value = match inline_value {
+                Some(value) => value,
+                None if boolean_keys.contains(&key) => "true".to_string(),
+                None => args.next().ok_or_else(|| {
+                    SecretaryError::Usage(format!("Missing value for --{}", name))
+                })?,
+            };
+
+            if key == "config" {
+                cli.config_path = PathBuf::from(value);
+            } else {
+                cli.overrides.push((key, value));
+            }
+        }
+
+        Ok(cli)
+    }
+}
@@ -0,0 +1,169 @@
+use crate::auth::auth::initialize;
+use crate::cache::cache::AudioCache;
+use crate::cli::cli::Cli;
+use crate::config::config::Config;
+use crate::dashboard::dashboard::ReprocessRequest;
+use crate::error::error::SecretaryError;
+use crate::ledger::ledger::{local_period, Ledger};
+use crate::models::usage::Usage;
+use crate::secretary::Secretary;
+use std::collections::BTreeMap;
+
+/// Runs a one-off command instead of the processing loop.
+pub async fn run(cli: &Cli) -> Result<(), SecretaryError> {
+    let words: Vec<&str> = cli.command.iter().map(String::as_str).collect();
+    match words.as_slice() {
+        ["reprocess", recording] => reprocess(cli, recording, None).await,
+        ["reprocess", recording, profile] => reprocess(cli, recording, Some(profile)).await,
+        ["secrets", "set", name] => secrets_set(cli, name),
+        ["secrets", "list"] => secrets_list(cli),
+        ["secrets", "migrate"] => secrets_migrate(cli),
+        ["usage"] | ["usage", "month"] => usage(cli, "%Y-%m"),
+        ["usage", "day"] => usage(cli, "%Y-%m-%d"),
+        ["cache", "clean"] => cache_clean(cli, false),
+        ["cache", "clean", "all"] => cache_clean(cli, true),
+        _ => Err(SecretaryError::Usage(format!(
+            "Unknown command '{}'",
+            cli.command.join(" ")
+        ))),
+    }
+}
+
+/// Reads a value from the terminal (without echo) and stores it in the secrets file.
+fn secrets_set(cli: &Cli, name: &str) -> Result<(), SecretaryError> {
+    let mut config = Config::load_layers(&cli.config_path, &cli.overrides)?;
+    let secrets_file = config.secrets_file.as_mut().ok_or_else(no_secrets_file)?;
+    let value = rpassword::prompt_password(format!("Value for '{}': ", name))?;
+    secrets_file.set(name, value.trim().to_string());
+    secrets_file.save()?;
+    println!(
+        "Stored '{}' in {}. Reference it with {{ secret = \"{}\" }}.",
+        name,
+        secrets_file.path().display(),
+        name
+    );
+    Ok(())
+}
+
+/// Lists the names (never the values) stored in the secrets file.
+fn secrets_list(cli: &Cli) -> Result<(), SecretaryError> {
+    let config = Config::load_layers(&cli.config_path, &cli.overrides)?;
+    let secrets_file = config.secrets_file.as_ref().ok_or_else(no_secrets_file)?;
+    for name in secrets_file.names() {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+/// Moves plaintext secrets from the config file into the secrets file.
+fn secrets_migrate(cli: &Cli) -> Result<(), SecretaryError> {
+    let mut config = Config::load_layers(&cli.config_path, &cli.overrides)?;
+    if config.secrets_file.is_none() {
+        return Err(no_secrets_file());
+    }
+    config.save()?;
+    println!(
+        "Plaintext secrets moved out of {}.",
+        cli.config_path.display()
+    );
+    Ok(())
+}
+
+/// Applies the cache retention policy now, or with `all` removes every cached
+/// recording that isn't being processed.
+fn cache_clean(cli: &Cli, all: bool) -> Result<(), SecretaryError> {
+    let config = Config::load_layers(&cli.config_path, &cli.overrides)?;
+    let ledger = Ledger::load(&config.storage.ledger_path)?;
+    let report = AudioCache::new(&config.storage).clean(&ledger, all)?;
+    println!(
+        "Removed {} cached recordings ({:.1} MB) from {}.",
+        report.files,
+        report.bytes as f64 / 1_048_576.0,
+        config.storage.cache_dir
+    );
+    Ok(())
+}
+
+/// Runs one recording from the ledger, given by Dropbox id or file name, through
+/// the pipeline again and regenerates its note. With `--dry-run` the changes to
+/// the note are printed instead; that still transcribes and analyzes the
+/// recording, so it needs `--allow-paid-calls`.
+async fn reprocess(
+    cli: &Cli,
+    recording: &str,
+    profile: Option<&str>,
+) -> Result<(), SecretaryError> {
+    let config = Config::load(&cli.config_path, &cli.overrides)?;
+    if config.dry_run && !cli.allow_paid_calls {
+        return Err(SecretaryError::Usage(
+            "reprocess --dry-run still downloads, transcribes and analyzes the recording, which OpenAI charges for. Add --allow-paid-calls to go ahead.".to_string(),
+        ));
+    }
+    let mut secretary = Secretary::new(config)?;
+    let request = {
+        let ledger = secretary.ledger.lock().unwrap();
+        let entry = ledger
+            .entries()
+            .iter()
+            .find(|entry| {
+                entry.audio_file_metadata.id == recording
+                    || entry.audio_file_metadata.name == recording
+            })
+            .ok_or_else(|| SecretaryError::Usage(format!("Unknown recording '{}'", recording)))?;
+        ReprocessRequest {
+            id: entry.audio_file_metadata.id.clone(),
+            profile: profile.map_or_else(|| entry.prompt_profile.clone(), str::to_string),
+        }
+    };
+    // Before authorizing, so a mistyped profile fails right away
+    secretary.queue_reprocess(request)?;
+    initialize(&mut secretary).await?;
+    secretary.process_audio_notes().await
+}
+
+/// Prints audio minutes, tokens and cost from the ledger's usage history, per
+/// `period` (a date format: one line per day or month, in local time).
+fn usage(cli: &Cli, period: &str) -> Result<(), SecretaryError> {
+    let config = Config::load_layers(&cli.config_path, &cli.overrides)?;
+    let ledger = Ledger::load(&config.storage.ledger_path)?;
+
+    let mut periods: BTreeMap<String, (usize, Usage, f64)> = BTreeMap::new();
+    for record in ledger.usage_records() {
+        let totals = periods
+            .entry(local_period(&record.recorded_at, period))
+            .or_default();
+        totals.0 += 1;
+        totals.1 += record.usage;
+        totals.2 += record.cost;
+    }
+
+    println!(
+        "{:<10} {:>5} {:>9} {:>12} {:>12} {:>10}",
+        "Period", "Runs", "Audio min", "Prompt tok", "Output tok", "Cost"
+    );
+    for (name, (runs, usage, cost)) in &periods {
+        println!(
+            "{:<10} {:>5} {:>9.1} {:>12} {:>12} {:>10}",
+            name,
+            runs,
+            usage.audio_minutes,
+            usage.prompt_tokens,
+            usage.completion_tokens,
+            format!("${:.2}", cost)
+        );
+    }
+
+    let budget = config.usage.monthly_budget;
+    if budget > 0.0 {
+        println!(
+            "This month: ${:.2} of the ${:.2} budget spent.",
+            ledger.cost_this_month(),
+            budget
+        );
+    }
+    Ok(())
+}
+
+fn no_secrets_file() -> SecretaryError {
+    SecretaryError::Config("secrets.file is not set".to_string())
+}
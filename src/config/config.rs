@@ -1,86 +1,1188 @@
 // TODO: Extract the prompt logic into a separate function to keep the Config struct focused. [[2]](https://poe.com/citation?message_id=175047583702&citation=2)
-// TODO: Consider validating the user input to ensure properly formatted tokens/keys are provided. [[2]](https://poe.com/citation?message_id=175047583702&citation=2)
 // TODO: Make the prompt messages configurable via the config file. [[2]](https://poe.com/citation?message_id=175047583702&citation=2)[[3]](https://poe.com/citation?message_id=175047583702&citation=3)
 
+use crate::config::migration::{self, CONFIG_VERSION, V1_KEYS};
+use crate::config::secrets::{Secret, SecretSource, SecretsFile};
+use crate::error::error::SecretaryError;
+use crate::models::usage::Usage;
+use chrono::format::{Item, StrftimeItems};
+use reqwest::Url;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// How Secretary obtains Dropbox authorization when no usable token is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DropboxAuthMode {
+    /// Open the authorization page and receive the code on a local callback server.
+    #[default]
+    Browser,
+    /// Print the authorization URL and read the code from stdin or the dashboard.
+    Headless,
+    /// Fail instead of waiting for a user, e.g. under systemd.
+    NonInteractive,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DropboxConfig {
+    pub client_id: String,
+    pub client_secret: Secret,
+    pub access_token: Secret,
+    pub refresh_token: Secret,
+    pub audio_path: String,
+    /// Also process recordings in folders below `audio_path`.
+    pub include_subfolders: bool,
+    pub auth_mode: DropboxAuthMode,
+    pub callback_host: String,
+    pub callback_port: u16,
+    pub auth_timeout_secs: u64,
+}
+
+impl Default for DropboxConfig {
+    fn default() -> Self {
+        DropboxConfig {
+            client_id: "".to_string(),
+            client_secret: Secret::default(),
+            access_token: Secret::default(),
+            refresh_token: Secret::default(),
+            audio_path: "".to_string(),
+            include_subfolders: false,
+            auth_mode: DropboxAuthMode::default(),
+            callback_host: "localhost".to_string(),
+            callback_port: 8080,
+            auth_timeout_secs: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenAiConfig {
+    pub api_key: Secret,
+    pub whisper_api_key: Secret,
+}
+
+/// How the original recording is made available from its note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AudioAttachment {
+    /// Only record the file name.
+    #[default]
+    None,
+    /// Copy (or transcode) the recording into the vault and embed it.
+    Copy,
+    /// Embed a Dropbox shared link to the recording.
+    SharedLink,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObsidianConfig {
+    pub vault_path: String,
+    pub audio_attachment: AudioAttachment,
+    /// Folder inside the vault that copied recordings go to.
+    pub attachments_dir: String,
+    /// Shell command that converts `{input}` to `{output}` before copying, e.g.
+    /// `ffmpeg -i {input} -b:a 48k {output}`. Empty to copy the original.
+    pub transcode_command: String,
+    /// Extension of the files `transcode_command` produces.
+    pub transcode_extension: String,
+    /// File name of new notes, from `{title}`, `{date}`, `{time}`, `{file}` and `{profile}`.
+    pub note_name_pattern: String,
+    /// Checked in order; the first matching route picks a new note's folder.
+    pub routes: Vec<RouteRule>,
+}
+
+impl Default for ObsidianConfig {
+    fn default() -> Self {
+        ObsidianConfig {
+            vault_path: "".to_string(),
+            audio_attachment: AudioAttachment::default(),
+            attachments_dir: "attachments".to_string(),
+            transcode_command: "".to_string(),
+            transcode_extension: "m4a".to_string(),
+            note_name_pattern: "{title}".to_string(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+/// Puts new notes that match it into a vault folder. Every condition that is
+/// set must match; a route without conditions matches every note.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteRule {
+    /// Tag in the note's frontmatter, without `#`.
+    pub tag: String,
+    /// The note's `category` (or `type`) frontmatter value.
+    pub category: String,
+    /// Someone in the note's `person` (or `people`, `attendees`) frontmatter list.
+    pub person: String,
+    /// Dropbox folder the recording is in, e.g. `/Recordings/Meetings`.
+    pub source_folder: String,
+    /// Vault folder, e.g. `Meetings/{year}`. Also takes `{month}`, `{day}`,
+    /// `{date}`, `{category}` and `{profile}`.
+    pub folder: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DashboardConfig {
+    pub port: u16,
+}
+
+impl Default for DashboardConfig {
+    fn default() -> Self {
+        DashboardConfig { port: 3030 }
+    }
+}
+
+/// When downloaded recordings are removed from the cache directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheRetention {
+    /// As soon as the note is saved.
+    DeleteAfterSave,
+    /// After `cache_keep_days` days.
+    #[default]
+    KeepDays,
+    /// Oldest first, once the cache grows beyond `cache_max_size_mb`.
+    MaxSize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub ledger_path: String,
+    /// Where downloaded recordings are kept.
+    pub cache_dir: String,
+    pub cache_retention: CacheRetention,
+    pub cache_keep_days: u64,
+    pub cache_max_size_mb: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            ledger_path: "ledger.json".to_string(),
+            cache_dir: "cache".to_string(),
+            cache_retention: CacheRetention::default(),
+            cache_keep_days: 7,
+            cache_max_size_mb: 1024,
+        }
+    }
+}
+
+/// How many recordings may be in each pipeline stage at the same time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PipelineConfig {
+    pub download_concurrency: usize,
+    pub transcription_concurrency: usize,
+    pub analysis_concurrency: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            download_concurrency: 4,
+            transcription_concurrency: 2,
+            analysis_concurrency: 2,
+        }
+    }
+}
+
+/// Timeouts, retries and per-provider limits for all API requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub connect_timeout_secs: u64,
+    /// Longest wait for the next bytes of a response. There is no limit on a
+    /// whole request, so large downloads and uploads can take as long as they
+    /// keep making progress.
+    #[serde(alias = "timeout_secs")]
+    pub read_timeout_secs: u64,
+    /// Retries after a network error, rate limit or server error.
+    pub max_retries: u32,
+    /// First backoff delay; doubled on every retry, with jitter.
+    pub backoff_base_ms: u64,
+    pub backoff_max_secs: u64,
+    pub dropbox_max_concurrent: usize,
+    pub dropbox_requests_per_minute: u32,
+    pub openai_max_concurrent: usize,
+    pub openai_requests_per_minute: u32,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            connect_timeout_secs: 10,
+            read_timeout_secs: 300,
+            max_retries: 5,
+            backoff_base_ms: 500,
+            backoff_max_secs: 60,
+            dropbox_max_concurrent: 4,
+            dropbox_requests_per_minute: 120,
+            openai_max_concurrent: 2,
+            openai_requests_per_minute: 60,
+        }
+    }
+}
+
+/// Picks a prompt profile for the recordings it matches. Every condition that
+/// is set must match.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileRule {
+    /// Dropbox folder the recording is in, e.g. `/Recordings/Meetings`.
+    pub folder: String,
+    /// File name pattern, where `*` matches any text and `?` one character.
+    pub file_pattern: String,
+    /// Word or phrase the recording starts with.
+    pub keyword: String,
+    pub profile: String,
+}
+
+/// Where prompt templates come from and how one is picked for a recording.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PromptsConfig {
+    /// Folder with one `<profile>.md` template per prompt profile.
+    pub dir: String,
+    /// Profile for recordings no rule matches.
+    pub default_profile: String,
+    /// People who usually speak in the recordings, for `{speakers}`.
+    pub speakers: Vec<String>,
+    /// How many recent note names `{vault_context}` lists.
+    pub vault_context_notes: usize,
+    /// Checked in order; the first matching rule picks the profile.
+    pub rules: Vec<ProfileRule>,
+}
+
+impl Default for PromptsConfig {
+    fn default() -> Self {
+        PromptsConfig {
+            dir: "prompts".to_string(),
+            default_profile: "default".to_string(),
+            speakers: Vec::new(),
+            vault_context_notes: 50,
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// A phrase that, spoken at the start of a recording, says how to process it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpokenCommand {
+    /// E.g. `meeting notes` or `journal entry`; case and punctuation don't matter.
+    pub phrase: String,
+    /// Prompt profile to use; empty to leave it to the profile rules.
+    pub profile: String,
+    /// Vault folder for the note; empty for the vault root.
+    pub folder: String,
+    /// Tags to add to the note.
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpokenCommandsConfig {
+    pub commands: Vec<SpokenCommand>,
+    /// When no phrase is found, ask the analysis model whether the recording
+    /// starts with one of the commands in other words.
+    pub classify_with_llm: bool,
+}
+
+/// Generation settings that a prompt profile may change. Unset values come
+/// from the `[analysis]` section.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalysisOverrides {
+    pub model: Option<String>,
+    pub fallback_models: Option<Vec<String>>,
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u32>,
+    pub top_p: Option<f64>,
+    pub seed: Option<u64>,
+    pub system_message: Option<String>,
+}
+
+/// How transcriptions are analyzed: the chat model and its parameters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    pub model: String,
+    /// Models to try in order when `model` fails or is unavailable.
+    pub fallback_models: Vec<String>,
+    pub temperature: f64,
+    /// Limit on the length of the note, unset for the model's maximum.
+    pub max_tokens: Option<u32>,
+    pub top_p: f64,
+    /// Makes the output mostly repeatable, if set.
+    pub seed: Option<u64>,
+    /// Sent before the prompt as the system message; empty for none.
+    pub system_message: String,
+    /// Settings for individual prompt profiles, by profile name.
+    pub profiles: BTreeMap<String, AnalysisOverrides>,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig {
+            model: "gpt-4o".to_string(),
+            fallback_models: Vec::new(),
+            temperature: 0.7,
+            max_tokens: None,
+            top_p: 1.0,
+            seed: None,
+            system_message: "".to_string(),
+            profiles: BTreeMap::new(),
+        }
+    }
+}
+
+impl AnalysisConfig {
+    /// The settings for `profile`: its overrides on top of the global ones.
+    pub fn settings_for(&self, profile: &str) -> GenerationSettings {
+        self.with_overrides(self.profiles.get(profile).cloned().unwrap_or_default())
+    }
+
+    fn with_overrides(&self, overrides: AnalysisOverrides) -> GenerationSettings {
+        GenerationSettings {
+            model: overrides.model.unwrap_or_else(|| self.model.clone()),
+            fallback_models: overrides
+                .fallback_models
+                .unwrap_or_else(|| self.fallback_models.clone()),
+            temperature: overrides.temperature.unwrap_or(self.temperature),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            top_p: overrides.top_p.unwrap_or(self.top_p),
+            seed: overrides.seed.or(self.seed),
+            system_message: overrides
+                .system_message
+                .unwrap_or_else(|| self.system_message.clone()),
+        }
+    }
+}
+
+/// The generation settings in effect for one analysis.
+#[derive(Debug, Clone)]
+pub struct GenerationSettings {
+    pub model: String,
+    pub fallback_models: Vec<String>,
+    pub temperature: f64,
+    pub max_tokens: Option<u32>,
+    pub top_p: f64,
+    pub seed: Option<u64>,
+    pub system_message: String,
+}
+
+impl GenerationSettings {
+    /// The model followed by its fallbacks, without repeats.
+    pub fn models(&self) -> Vec<&str> {
+        let mut models: Vec<&str> = Vec::new();
+        for model in std::iter::once(&self.model).chain(&self.fallback_models) {
+            if !models.contains(&model.as_str()) {
+                models.push(model);
+            }
+        }
+        models
+    }
+}
+
+/// Price of one OpenAI model in USD.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelPrice {
+    pub input_per_million_tokens: f64,
+    pub output_per_million_tokens: f64,
+    /// For transcription models.
+    pub per_audio_minute: f64,
+}
+
+/// Links each saved note from the daily note of the day it was recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DailyNotesConfig {
+    pub enabled: bool,
+    /// Daily note path inside the vault, as a strftime format.
+    pub path_format: String,
+    /// Heading the entries go under; added if the daily note lacks it.
+    pub heading: String,
+    /// Note inside the vault that new daily notes start from; `{{date}}` and
+    /// `{{title}}` in it are filled in. Empty to start with just the heading.
+    pub template: String,
+}
+
+impl Default for DailyNotesConfig {
+    fn default() -> Self {
+        DailyNotesConfig {
+            enabled: false,
+            path_format: "Daily/%Y-%m-%d.md".to_string(),
+            heading: "## Recordings".to_string(),
+            template: "".to_string(),
+        }
+    }
+}
+
+/// Collects the action items of every note in one task file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TasksConfig {
+    pub enabled: bool,
+    /// Task file inside the vault.
+    pub file: String,
+    /// Heading the items go under; added if the file lacks it.
+    pub heading: String,
+}
+
+impl Default for TasksConfig {
+    fn default() -> Self {
+        TasksConfig {
+            enabled: false,
+            file: "Tasks.md".to_string(),
+            heading: "## Action items".to_string(),
+        }
+    }
+}
+
+/// Prices to compute what each recording costs, and a limit on spending.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageConfig {
+    /// Prices by model name.
+    pub prices: BTreeMap<String, ModelPrice>,
+    /// Spending limit per calendar month in USD, 0 for none. Processing pauses
+    /// once it is reached.
+    pub monthly_budget: f64,
+    /// Add audio minutes, tokens and cost to the note's frontmatter.
+    pub write_to_frontmatter: bool,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        let price = |input, output, per_audio_minute| ModelPrice {
+            input_per_million_tokens: input,
+            output_per_million_tokens: output,
+            per_audio_minute,
+        };
+        UsageConfig {
+            prices: BTreeMap::from([
+                ("whisper-1".to_string(), price(0.0, 0.0, 0.006)),
+                ("gpt-4o".to_string(), price(5.0, 15.0, 0.0)),
+                ("gpt-4o-mini".to_string(), price(0.15, 0.6, 0.0)),
+                ("gpt-4-turbo".to_string(), price(10.0, 30.0, 0.0)),
+                ("gpt-4".to_string(), price(30.0, 60.0, 0.0)),
+                ("gpt-3.5-turbo".to_string(), price(0.5, 1.5, 0.0)),
+            ]),
+            monthly_budget: 0.0,
+            write_to_frontmatter: false,
+        }
+    }
+}
+
+impl UsageConfig {
+    /// Cost in USD of `usage` on `model`. Models without a price cost nothing,
+    /// with a warning.
+    pub fn cost(&self, model: &str, usage: &Usage) -> f64 {
+        let Some(price) = self.prices.get(model) else {
+            eprintln!(
+                "No price for model '{}' in [usage.prices]; counting its cost as 0",
+                model
+            );
+            return 0.0;
+        };
+        (usage.prompt_tokens as f64 * price.input_per_million_tokens
+            + usage.completion_tokens as f64 * price.output_per_million_tokens)
+            / 1_000_000.0
+            + usage.audio_minutes * price.per_audio_minute
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SecretsConfig {
+    /// Passphrase-encrypted secrets file; empty to not use one.
+    pub file: String,
+    /// Passphrase for `file`. Prompted for on a terminal when empty.
+    pub passphrase: Secret,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
-    pub dropbox_client_id: String,
-    pub dropbox_client_secret: String,
-    pub dropbox_access_token: String,
-    pub dropbox_auth_token: String,
-    pub whisper_api_key: String,
-    pub openai_api_key: String,
-    pub obsidian_vault_path: String,
-    pub dropbox_audio_path: String,
+    pub config_version: i64,
+    /// Never prompt on stdin; fail with the list of missing keys instead.
+    pub non_interactive: bool,
+    /// Show what would be done instead of processing recordings or writing notes.
+    pub dry_run: bool,
+    /// Seconds to wait between two checks for new recordings.
+    pub poll_interval_secs: u64,
+    pub dropbox: DropboxConfig,
+    pub openai: OpenAiConfig,
+    pub obsidian: ObsidianConfig,
+    pub dashboard: DashboardConfig,
+    pub storage: StorageConfig,
+    pub pipeline: PipelineConfig,
+    pub http: HttpConfig,
+    pub prompts: PromptsConfig,
+    pub spoken_commands: SpokenCommandsConfig,
+    pub analysis: AnalysisConfig,
+    pub usage: UsageConfig,
+    pub daily_notes: DailyNotesConfig,
+    pub tasks: TasksConfig,
+    pub secrets: SecretsConfig,
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(skip)]
+    pub secrets_file: Option<SecretsFile>,
+    /// Contents of the config file, kept so `save` doesn't write values that came
+    /// from the defaults, the environment or the command line.
+    #[serde(skip)]
+    file_table: Table,
+    /// All layers as loaded, to tell which values changed since.
+    #[serde(skip)]
+    loaded_table: Table,
+    #[serde(skip)]
+    overridden_keys: HashSet<String>,
 }
 
-// TODO: Update the flow to take into account the Oauth2 logic.
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            config_version: CONFIG_VERSION,
+            non_interactive: false,
+            dry_run: false,
+            poll_interval_secs: 60,
+            dropbox: DropboxConfig::default(),
+            openai: OpenAiConfig::default(),
+            obsidian: ObsidianConfig::default(),
+            dashboard: DashboardConfig::default(),
+            storage: StorageConfig::default(),
+            pipeline: PipelineConfig::default(),
+            http: HttpConfig::default(),
+            prompts: PromptsConfig::default(),
+            spoken_commands: SpokenCommandsConfig::default(),
+            analysis: AnalysisConfig::default(),
+            usage: UsageConfig::default(),
+            daily_notes: DailyNotesConfig::default(),
+            tasks: TasksConfig::default(),
+            secrets: SecretsConfig::default(),
+            path: PathBuf::new(),
+            secrets_file: None,
+            file_table: Table::new(),
+            loaded_table: Table::new(),
+            overridden_keys: HashSet::new(),
+        }
+    }
+}
+
+/// Prefix of the environment variables (and `.env` entries) that override config keys,
+/// e.g. `SECRETARY_OPENAI_API_KEY` for `openai.api_key`.
+pub const ENV_PREFIX: &str = "SECRETARY_";
+
 impl Config {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut config: Config;
-
-        if !path.as_ref().exists() {
-            println!("Config file not found. Creating a new one.");
-            config = Config {
-                dropbox_access_token: "".to_string(),
-                dropbox_auth_token: "".to_string(),
-                whisper_api_key: "".to_string(),
-                openai_api_key: "".to_string(),
-                obsidian_vault_path: "".to_string(),
-                dropbox_audio_path: "".to_string(),
-                dropbox_client_id: "".to_string(),
-                dropbox_client_secret: "".to_string(),
-            };
-            config.prompt_and_update_if_empty();
-            let toml = toml::to_string(&config)?;
-            fs::write(&path, toml)?;
-            println!("Config file created at: {}", path.as_ref().display());
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SecretaryError> {
+        Config::load(path, &[])
+    }
+
+    /// Loads the configuration in layers, each overriding the previous one:
+    /// defaults, the config file, `SECRETARY_*` entries in `.env`, `SECRETARY_*`
+    /// environment variables, and finally `overrides` from the command line.
+    /// Older config files are migrated to the current layout first. Fails when
+    /// required keys are missing (after prompting, on a terminal) or invalid.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        overrides: &[(String, String)],
+    ) -> Result<Self, SecretaryError> {
+        let mut config = Config::load_layers(&path, overrides)?;
+        let missing = config.missing_keys();
+        if missing.is_empty() {
+            println!("Configuration loaded from: {}", path.as_ref().display());
+        } else if config.non_interactive || !std::io::stdin().is_terminal() {
+            return Err(SecretaryError::Config(format!(
+                "missing required keys: {}. Set them in {}, .env, {}* environment variables or command line flags.",
+                missing.join(", "),
+                path.as_ref().display(),
+                ENV_PREFIX
+            )));
         } else {
-            let file_contents = fs::read_to_string(&path)?;
-            config = toml::from_str(&file_contents)?;
-            if config.has_empty_values() {
-                println!("Config file has empty values. Prompting for missing values.");
-                config.prompt_and_update_if_empty();
-                let toml = toml::to_string(&config)?;
-                fs::write(&path, toml)?;
-                println!("Config file updated at: {}", path.as_ref().display());
-            } else {
-                println!("Config file loaded from: {}", path.as_ref().display());
+            println!("Configuration has empty values. Prompting for missing values.");
+            config.prompt_and_update_if_empty();
+            config.save()?;
+            println!("Config file updated at: {}", path.as_ref().display());
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Loads and merges all layers and resolves secrets, without checking for
+    /// missing or invalid values.
+    pub fn load_layers<P: AsRef<Path>>(
+        path: P,
+        overrides: &[(String, String)],
+    ) -> Result<Self, SecretaryError> {
+        let defaults = Table::try_from(Config::default())?;
+        let mut table = defaults.clone();
+
+        let mut file_table = Table::new();
+        if path.as_ref().exists() {
+            file_table = toml::from_str(&fs::read_to_string(&path)?)?;
+            let version = migration::file_version(&file_table);
+            if migration::migrate(&mut file_table)? {
+                let backup_path = migration::backup(path.as_ref(), version)?;
+                fs::write(&path, toml::to_string(&file_table)?)?;
+                println!(
+                    "Config file migrated from version {} to {}. Original saved to: {}",
+                    version,
+                    CONFIG_VERSION,
+                    backup_path.display()
+                );
             }
         }
+        merge(&mut table, file_table.clone());
+
+        // `.env` never overrides variables already set in the environment, which
+        // gives it the lower precedence. Unknown SECRETARY_* variables are only
+        // warned about, unknown flags are errors.
+        dotenv::dotenv().ok();
+        let mut layered: Vec<(String, String, bool)> = std::env::vars()
+            .filter_map(env_key)
+            .map(|(key, value)| (key, value, false))
+            .collect();
+        layered.extend(
+            overrides
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone(), true)),
+        );
+
+        let mut overridden_keys = HashSet::new();
+        for (name, value, strict) in layered {
+            let key = match resolve_key(&defaults, &name) {
+                Some(key) => key,
+                None if strict => {
+                    return Err(SecretaryError::Config(format!("unknown key: '{}'", name)))
+                }
+                None => {
+                    eprintln!(
+                        "Ignoring unknown configuration key from environment: '{}'",
+                        name
+                    );
+                    continue;
+                }
+            };
+            let value = match migration::get_path(&defaults, &key) {
+                Some(Value::Integer(_)) => Value::Integer(value.parse().map_err(|_| {
+                    SecretaryError::Config(format!(
+                        "invalid value for '{}': expected a number, got '{}'",
+                        key, value
+                    ))
+                })?),
+                Some(Value::Float(_)) => Value::Float(value.parse().map_err(|_| {
+                    SecretaryError::Config(format!(
+                        "invalid value for '{}': expected a number, got '{}'",
+                        key, value
+                    ))
+                })?),
+                Some(Value::Array(_)) => Value::Array(
+                    value
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(|item| Value::String(item.to_string()))
+                        .collect(),
+                ),
+                Some(Value::Boolean(_)) => Value::Boolean(value.parse().map_err(|_| {
+                    SecretaryError::Config(format!(
+                        "invalid value for '{}': expected true or false, got '{}'",
+                        key, value
+                    ))
+                })?),
+                _ => Value::String(value),
+            };
+            migration::set_path(&mut table, &key, value);
+            overridden_keys.insert(key);
+        }
 
+        let mut config: Config = table.try_into()?;
+        config.path = path.as_ref().to_path_buf();
+        config.loaded_table = Table::try_from(&config)?;
+        config.file_table = file_table;
+        config.overridden_keys = overridden_keys;
+        config.resolve_secrets()?;
         Ok(config)
     }
 
-    fn has_empty_values(&self) -> bool {
-        self.dropbox_access_token.is_empty()
-            || self.whisper_api_key.is_empty()
-            || self.openai_api_key.is_empty()
-            || self.obsidian_vault_path.is_empty()
-            || self.dropbox_client_id.is_empty()
-            || self.dropbox_client_secret.is_empty()
-            || self.dropbox_audio_path.is_empty()
+    /// Flat names of the boolean keys (e.g. `non_interactive`), so command line
+    /// flags for them need no value.
+    pub fn boolean_keys() -> Vec<String> {
+        fn collect(table: &Table, prefix: &str, keys: &mut Vec<String>) {
+            for (key, value) in table {
+                match value {
+                    Value::Boolean(_) => keys.push(format!("{}{}", prefix, key)),
+                    Value::Table(section) => collect(section, &format!("{}_", key), keys),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        if let Ok(table) = Table::try_from(Config::default()) {
+            collect(&table, "", &mut keys);
+        }
+        keys
+    }
+
+    /// Every secret in the config, with its key path.
+    fn secret_fields(&mut self) -> [(&'static str, &mut Secret); 5] {
+        [
+            ("dropbox.client_secret", &mut self.dropbox.client_secret),
+            ("dropbox.access_token", &mut self.dropbox.access_token),
+            ("dropbox.refresh_token", &mut self.dropbox.refresh_token),
+            ("openai.api_key", &mut self.openai.api_key),
+            ("openai.whisper_api_key", &mut self.openai.whisper_api_key),
+        ]
+    }
+
+    /// Opens the secrets file, if one is configured, and resolves every secret
+    /// reference to its value.
+    fn resolve_secrets(&mut self) -> Result<(), SecretaryError> {
+        self.secrets.passphrase.resolve(None)?;
+        if !self.secrets.file.is_empty() {
+            let mut passphrase = self.secrets.passphrase.expose().to_string();
+            if passphrase.is_empty() {
+                if self.non_interactive || !std::io::stdin().is_terminal() {
+                    return Err(SecretaryError::Config(format!(
+                        "secrets.file is set but no passphrase is available; set secrets.passphrase or {}SECRETS_PASSPHRASE",
+                        ENV_PREFIX
+                    )));
+                }
+                passphrase = rpassword::prompt_password("Secrets file passphrase: ")?;
+            }
+            self.secrets_file = Some(SecretsFile::open(&self.secrets.file, passphrase)?);
+        }
+
+        let secrets_file = self.secrets_file.take();
+        for (key, secret) in self.secret_fields() {
+            secret.resolve(secrets_file.as_ref()).map_err(|error| {
+                SecretaryError::Config(format!("failed to resolve {}: {}", key, error))
+            })?;
+        }
+        self.secrets_file = secrets_file;
+        Ok(())
+    }
+
+    /// Writes the values changed since loading, such as new OAuth tokens, to the
+    /// file the config was loaded from. Everything else in the file is kept as it
+    /// was, and defaults and keys set through the environment or command line are
+    /// not added. The file holds OAuth tokens, so it is only readable by the
+    /// current user. With a secrets file, plain secrets are moved into it and only
+    /// referenced from the config.
+    pub fn save(&mut self) -> Result<(), SecretaryError> {
+        if let Some(mut secrets_file) = self.secrets_file.take() {
+            let overridden_keys = self.overridden_keys.clone();
+            let mut changed = false;
+            for (key, secret) in self.secret_fields() {
+                if overridden_keys.contains(key) {
+                    continue;
+                }
+                match secret.source().clone() {
+                    SecretSource::Plain(value) if !value.is_empty() => {
+                        secret.store_in(&mut secrets_file, key);
+                        changed = true;
+                    }
+                    SecretSource::SecretsFile { secret: name }
+                        if secrets_file.get(&name) != Some(secret.expose()) =>
+                    {
+                        secret.store_in(&mut secrets_file, &name);
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if changed {
+                secrets_file.save()?;
+            }
+            self.secrets_file = Some(secrets_file);
+        }
+
+        let current = Table::try_from(&*self)?;
+        let mut table = self.file_table.clone();
+        let mut changed = Vec::new();
+        changed_values(&self.loaded_table, &current, "", &mut changed);
+        for (key, value) in changed {
+            let overridden = self.overridden_keys.iter().any(|overridden| {
+                key == *overridden || key.starts_with(&format!("{}.", overridden))
+            });
+            if !overridden {
+                migration::set_path(&mut table, &key, value);
+            }
+        }
+        table
+            .entry("config_version")
+            .or_insert(Value::Integer(CONFIG_VERSION));
+
+        fs::write(&self.path, toml::to_string(&table)?)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+        }
+        self.file_table = table;
+        self.loaded_table = current;
+        Ok(())
+    }
+
+    /// Required keys that are still empty after all layers are applied.
+    pub fn missing_keys(&self) -> Vec<&'static str> {
+        let required = [
+            (
+                "openai.whisper_api_key",
+                self.openai.whisper_api_key.expose(),
+            ),
+            ("openai.api_key", self.openai.api_key.expose()),
+            ("obsidian.vault_path", self.obsidian.vault_path.as_str()),
+            ("dropbox.client_id", self.dropbox.client_id.as_str()),
+            ("dropbox.audio_path", self.dropbox.audio_path.as_str()),
+        ];
+        required
+            .into_iter()
+            .filter(|(_, value)| value.is_empty())
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Checks the values themselves, reporting every problem at once.
+    pub fn validate(&self) -> Result<(), SecretaryError> {
+        let mut problems = Vec::new();
+
+        let vault_path = Path::new(&self.obsidian.vault_path);
+        if !vault_path.is_dir() {
+            problems.push(format!(
+                "obsidian.vault_path '{}' is not an existing directory",
+                vault_path.display()
+            ));
+        } else if tempfile::NamedTempFile::new_in(vault_path).is_err() {
+            problems.push(format!(
+                "obsidian.vault_path '{}' is not writable",
+                vault_path.display()
+            ));
+        }
+
+        if self.obsidian.note_name_pattern.trim().is_empty() {
+            problems.push("obsidian.note_name_pattern must not be empty".to_string());
+        }
+
+        if self.obsidian.audio_attachment == AudioAttachment::Copy {
+            if Path::new(&self.obsidian.attachments_dir).is_absolute()
+                || self.obsidian.attachments_dir.contains("..")
+            {
+                problems
+                    .push("obsidian.attachments_dir must be a folder inside the vault".to_string());
+            }
+            let command = &self.obsidian.transcode_command;
+            let placeholders = command.contains("{input}") && command.contains("{output}");
+            if !command.is_empty() && !placeholders {
+                problems.push(
+                    "obsidian.transcode_command must contain {input} and {output}".to_string(),
+                );
+            }
+        }
+
+        if !self.dropbox.audio_path.starts_with('/') || self.dropbox.audio_path.ends_with('/') {
+            problems.push(format!(
+                "dropbox.audio_path '{}' must start with '/' and not end with '/'",
+                self.dropbox.audio_path
+            ));
+        }
+
+        if !self
+            .dropbox
+            .client_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric())
+        {
+            problems.push("dropbox.client_id must only contain letters and digits".to_string());
+        }
+
+        for (key, value) in [
+            ("openai.api_key", self.openai.api_key.expose()),
+            (
+                "openai.whisper_api_key",
+                self.openai.whisper_api_key.expose(),
+            ),
+        ] {
+            if !value.starts_with("sk-") || value.chars().any(char::is_whitespace) {
+                problems.push(format!(
+                    "{} does not look like an OpenAI key (expected 'sk-...')",
+                    key
+                ));
+            }
+        }
+
+        let callback_url = format!(
+            "http://{}:{}/callback",
+            self.dropbox.callback_host, self.dropbox.callback_port
+        );
+        if Url::parse(&callback_url).is_err() {
+            problems.push(format!(
+                "dropbox.callback_host and dropbox.callback_port form an invalid URL: {}",
+                callback_url
+            ));
+        }
+
+        for (key, port) in [
+            ("dropbox.callback_port", self.dropbox.callback_port),
+            ("dashboard.port", self.dashboard.port),
+        ] {
+            if port == 0 {
+                problems.push(format!("{} must not be 0", key));
+            }
+        }
+
+        for (key, value, min, max) in [
+            ("poll_interval_secs", self.poll_interval_secs, 10, 86_400),
+            (
+                "dropbox.auth_timeout_secs",
+                self.dropbox.auth_timeout_secs,
+                10,
+                3_600,
+            ),
+            (
+                "prompts.vault_context_notes",
+                self.prompts.vault_context_notes as u64,
+                0,
+                1_000,
+            ),
+            (
+                "storage.cache_keep_days",
+                self.storage.cache_keep_days,
+                0,
+                3_650,
+            ),
+            (
+                "storage.cache_max_size_mb",
+                self.storage.cache_max_size_mb,
+                1,
+                10_000_000,
+            ),
+            (
+                "pipeline.download_concurrency",
+                self.pipeline.download_concurrency as u64,
+                1,
+                64,
+            ),
+            (
+                "pipeline.transcription_concurrency",
+                self.pipeline.transcription_concurrency as u64,
+                1,
+                64,
+            ),
+            (
+                "pipeline.analysis_concurrency",
+                self.pipeline.analysis_concurrency as u64,
+                1,
+                64,
+            ),
+            (
+                "http.connect_timeout_secs",
+                self.http.connect_timeout_secs,
+                1,
+                300,
+            ),
+            (
+                "http.read_timeout_secs",
+                self.http.read_timeout_secs,
+                1,
+                3_600,
+            ),
+            ("http.max_retries", self.http.max_retries as u64, 0, 20),
+            ("http.backoff_base_ms", self.http.backoff_base_ms, 1, 60_000),
+            (
+                "http.backoff_max_secs",
+                self.http.backoff_max_secs,
+                1,
+                3_600,
+            ),
+            (
+                "http.dropbox_max_concurrent",
+                self.http.dropbox_max_concurrent as u64,
+                1,
+                64,
+            ),
+            (
+                "http.dropbox_requests_per_minute",
+                self.http.dropbox_requests_per_minute as u64,
+                1,
+                10_000,
+            ),
+            (
+                "http.openai_max_concurrent",
+                self.http.openai_max_concurrent as u64,
+                1,
+                64,
+            ),
+            (
+                "http.openai_requests_per_minute",
+                self.http.openai_requests_per_minute as u64,
+                1,
+                10_000,
+            ),
+        ] {
+            if !(min..=max).contains(&value) {
+                problems.push(format!(
+                    "{} must be between {} and {}, got {}",
+                    key, min, max, value
+                ));
+            }
+        }
+
+        for (index, rule) in self.prompts.rules.iter().enumerate() {
+            if rule.folder.is_empty() && rule.file_pattern.is_empty() && rule.keyword.is_empty() {
+                problems.push(format!(
+                    "prompts.rules[{}] needs a folder, file_pattern or keyword",
+                    index
+                ));
+            }
+        }
+
+        for (index, route) in self.obsidian.routes.iter().enumerate() {
+            if route.folder.trim().is_empty() {
+                problems.push(format!("obsidian.routes[{}] needs a folder", index));
+            } else if !is_vault_folder(&route.folder) {
+                problems.push(format!(
+                    "obsidian.routes[{}].folder must be a folder inside the vault",
+                    index
+                ));
+            }
+        }
+
+        let daily_notes = &self.daily_notes;
+        if StrftimeItems::new(&daily_notes.path_format).any(|item| matches!(item, Item::Error)) {
+            problems.push(format!(
+                "daily_notes.path_format is not a valid date format: {}",
+                daily_notes.path_format
+            ));
+        } else if !is_vault_folder(&daily_notes.path_format) {
+            problems.push("daily_notes.path_format must be a path inside the vault".to_string());
+        }
+        if !is_vault_folder(&daily_notes.template) {
+            problems.push("daily_notes.template must be a note inside the vault".to_string());
+        }
+        if daily_notes.heading.trim().is_empty() {
+            problems.push("daily_notes.heading must not be empty".to_string());
+        }
+
+        if self.tasks.file.trim().is_empty() || !is_vault_folder(&self.tasks.file) {
+            problems.push("tasks.file must be a note inside the vault".to_string());
+        }
+        if self.tasks.heading.trim().is_empty() {
+            problems.push("tasks.heading must not be empty".to_string());
+        }
+
+        for (index, command) in self.spoken_commands.commands.iter().enumerate() {
+            if !command.phrase.chars().any(char::is_alphanumeric) {
+                problems.push(format!(
+                    "spoken_commands.commands[{}].phrase must contain a word",
+                    index
+                ));
+            }
+            if !is_vault_folder(&command.folder) {
+                problems.push(format!(
+                    "spoken_commands.commands[{}].folder must be a folder inside the vault",
+                    index
+                ));
+            }
+        }
+
+        let mut analysis_settings = vec![(
+            "analysis".to_string(),
+            self.analysis.with_overrides(AnalysisOverrides::default()),
+        )];
+        for name in self.analysis.profiles.keys() {
+            analysis_settings.push((
+                format!("analysis.profiles.{}", name),
+                self.analysis.settings_for(name),
+            ));
+        }
+        for (section, settings) in analysis_settings {
+            if settings
+                .models()
+                .iter()
+                .any(|model| model.trim().is_empty())
+            {
+                problems.push(format!("{}: model names must not be empty", section));
+            }
+            if !(0.0..=2.0).contains(&settings.temperature) {
+                problems.push(format!(
+                    "{}.temperature must be between 0 and 2, got {}",
+                    section, settings.temperature
+                ));
+            }
+            if !(0.0..=1.0).contains(&settings.top_p) {
+                problems.push(format!(
+                    "{}.top_p must be between 0 and 1, got {}",
+                    section, settings.top_p
+                ));
+            }
+            if settings.max_tokens == Some(0) {
+                problems.push(format!("{}.max_tokens must not be 0", section));
+            }
+        }
+
+        let valid_amount = |amount: f64| amount.is_finite() && amount >= 0.0;
+        if !valid_amount(self.usage.monthly_budget) {
+            problems.push("usage.monthly_budget must not be negative".to_string());
+        }
+        for (model, price) in &self.usage.prices {
+            let amounts = [
+                price.input_per_million_tokens,
+                price.output_per_million_tokens,
+                price.per_audio_minute,
+            ];
+            if !amounts.into_iter().all(valid_amount) {
+                problems.push(format!("usage.prices.{} must not be negative", model));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SecretaryError::Config(format!(
+                "invalid values:\n  - {}",
+                problems.join("\n  - ")
+            )))
+        }
     }
 
     fn prompt_and_update_if_empty(&mut self) {
-        self.dropbox_access_token =
-            Config::prompt_if_empty(self.dropbox_access_token.clone(), "Dropbox Access Token");
-        self.whisper_api_key =
-            Config::prompt_if_empty(self.whisper_api_key.clone(), "Whisper API Key");
-        self.openai_api_key =
-            Config::prompt_if_empty(self.openai_api_key.clone(), "OpenAI API Key");
-        self.obsidian_vault_path =
-            Config::prompt_if_empty(self.obsidian_vault_path.clone(), "Obsidian Vault Path");
-        self.dropbox_client_id =
-            Config::prompt_if_empty(self.dropbox_client_id.clone(), "Dropbox Client ID");
-        self.dropbox_client_secret =
-            Config::prompt_if_empty(self.dropbox_client_secret.clone(), "Dropbox Client Secret");
-        self.dropbox_audio_path =
-            Config::prompt_if_empty(self.dropbox_audio_path.clone(), "Dropbox Audio Path");
+        Config::prompt_secret_if_empty(&mut self.openai.whisper_api_key, "Whisper API Key");
+        Config::prompt_secret_if_empty(&mut self.openai.api_key, "OpenAI API Key");
+        self.obsidian.vault_path =
+            Config::prompt_if_empty(self.obsidian.vault_path.clone(), "Obsidian Vault Path");
+        self.dropbox.client_id =
+            Config::prompt_if_empty(self.dropbox.client_id.clone(), "Dropbox Client ID");
+        Config::prompt_secret_if_empty(
+            &mut self.dropbox.client_secret,
+            "Dropbox Client Secret (optional with PKCE, leave empty to skip)",
+        );
+        self.dropbox.audio_path =
+            Config::prompt_if_empty(self.dropbox.audio_path.clone(), "Dropbox Audio Path");
+    }
+
+    /// Like `prompt_if_empty`, without echoing the input.
+    fn prompt_secret_if_empty(secret: &mut Secret, prompt: &str) {
+        if secret.is_empty() {
+            let input = rpassword::prompt_password(format!("Please enter your {}: ", prompt))
+                .expect("Failed to read line");
+            secret.set(input.trim().to_string());
+        }
     }
 
     fn prompt_if_empty(value: String, prompt: &str) -> String {
@@ -96,3 +1198,67 @@ impl Config {
         }
     }
 }
+
+/// Whether `folder` is a relative path that stays inside the vault.
+fn is_vault_folder(folder: &str) -> bool {
+    !Path::new(folder).is_absolute() && !folder.split(['/', '\\']).any(|part| part == "..")
+}
+
+/// Maps `SECRETARY_OPENAI_API_KEY=...` to `("openai_api_key", ...)`; other variables are ignored.
+fn env_key((name, value): (String, String)) -> Option<(String, String)> {
+    name.strip_prefix(ENV_PREFIX)
+        .map(|key| (key.to_lowercase(), value))
+}
+
+/// Resolves an override name to a dotted key path. Accepts dotted paths
+/// (`dropbox.client_id`), section-prefixed names (`dropbox_client_id`) and the
+/// names used before the config had sections (`whisper_api_key`).
+fn resolve_key(defaults: &Table, name: &str) -> Option<String> {
+    if name.contains('.') {
+        return migration::get_path(defaults, name)
+            .filter(|value| !value.is_table())
+            .map(|_| name.to_string());
+    }
+    if let Some((_, path)) = V1_KEYS.iter().find(|(old, _)| *old == name) {
+        return Some(path.to_string());
+    }
+    if defaults.get(name).is_some_and(|value| !value.is_table()) {
+        return Some(name.to_string());
+    }
+    defaults.iter().find_map(|(section, value)| {
+        let key = name.strip_prefix(&format!("{}_", section))?;
+        value
+            .as_table()?
+            .get(key)
+            .filter(|value| !value.is_table())
+            .map(|_| format!("{}.{}", section, key))
+    })
+}
+
+/// Collects the dotted paths of the values in `new` that differ from `old`.
+fn changed_values(old: &Table, new: &Table, prefix: &str, changed: &mut Vec<(String, Value)>) {
+    for (key, value) in new {
+        let path = format!("{}{}", prefix, key);
+        match (old.get(key), value) {
+            (Some(Value::Table(old_section)), Value::Table(section)) => {
+                changed_values(old_section, section, &format!("{}.", path), changed)
+            }
+            (Some(old_value), value) if old_value == value => {}
+            (_, value) => changed.push((path, value.clone())),
+        }
+    }
+}
+
+/// Recursively overlays `overlay` onto `base`, section by section.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_section)), Value::Table(section)) => {
+                merge(base_section, section)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crate::error::error::SecretaryError;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Version of the config file layout written by this build.
+pub const CONFIG_VERSION: i64 = 2;
+
+/// Where each key of the flat version 1 layout lives in the sectioned layout.
+/// Also used to resolve the old names given as environment variables or flags.
+pub const V1_KEYS: &[(&str, &str)] = &[
+    ("dropbox_client_id", "dropbox.client_id"),
+    ("dropbox_client_secret", "dropbox.client_secret"),
+    ("dropbox_access_token", "dropbox.access_token"),
+    ("dropbox_refresh_token", "dropbox.refresh_token"),
+    ("dropbox_audio_path", "dropbox.audio_path"),
+    ("dropbox_auth_mode", "dropbox.auth_mode"),
+    ("dropbox_callback_host", "dropbox.callback_host"),
+    ("dropbox_callback_port", "dropbox.callback_port"),
+    ("dropbox_auth_timeout_secs", "dropbox.auth_timeout_secs"),
+    ("whisper_api_key", "openai.whisper_api_key"),
+    ("openai_api_key", "openai.api_key"),
+    ("obsidian_vault_path", "obsidian.vault_path"),
+    ("dashboard_port", "dashboard.port"),
+    ("ledger_path", "storage.ledger_path"),
+    ("non_interactive", "non_interactive"),
+];
+
+/// Version 1 keys that were never used and are dropped on migration.
+const V1_UNUSED_KEYS: &[&str] = &["dropbox_auth_token"];
+
+/// Version of a parsed config file. Files without `config_version` predate it.
+pub fn file_version(table: &Table) -> i64 {
+    table
+        .get("config_version")
+        .and_then(Value::as_integer)
+        .unwrap_or(1)
+}
+
+/// Upgrades `table` in place to `CONFIG_VERSION`. Returns whether anything changed.
+pub fn migrate(table: &mut Table) -> Result<bool, SecretaryError> {
+    let version = file_version(table);
+    if version > CONFIG_VERSION {
+        return Err(SecretaryError::Config(format!(
+            "config file version {} is newer than supported version {}",
+            version, CONFIG_VERSION
+        )));
+    }
+    if version == CONFIG_VERSION {
+        return Ok(false);
+    }
+
+    let mut migrated = Table::new();
+    migrated.insert("config_version".to_string(), Value::Integer(CONFIG_VERSION));
+    for (key, value) in std::mem::take(table) {
+        if V1_UNUSED_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        match V1_KEYS.iter().find(|(old, _)| *old == key) {
+            Some((_, new_path)) => set_path(&mut migrated, new_path, value),
+            None => {
+                eprintln!("Dropping unknown key '{}' during config migration", key);
+            }
+        }
+    }
+    *table = migrated;
+    Ok(true)
+}
+
+/// Copies the config file next to itself before it gets rewritten, e.g.
+/// `config.toml` to `config.toml.v1.bak`.
+pub fn backup(path: &Path, version: i64) -> Result<PathBuf, SecretaryError> {
+    let mut backup_name = path.as_os_str().to_owned();
+    backup_name.push(format!(".v{}.bak", version));
+    let backup_path = PathBuf::from(backup_name);
+    fs::copy(path, &backup_path)?;
+    Ok(backup_path)
+}
+
+/// Looks up a dotted path such as `dropbox.client_id`.
+pub fn get_path<'a>(table: &'a Table, path: &str) -> Option<&'a Value> {
+    let (sections, key) = split_path(path);
+    let mut current = table;
+    for section in sections {
+        current = current.get(section)?.as_table()?;
+    }
+    current.get(key)
+}
+
+/// Sets a dotted path such as `dropbox.client_id`, creating sections as needed.
+pub fn set_path(table: &mut Table, path: &str, value: Value) {
+    let (sections, key) = split_path(path);
+    let mut current = table;
+    for section in sections {
+        let entry = current
+            .entry(section.to_string())
+            .or_insert_with(|| Value::Table(Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+        current = entry.as_table_mut().unwrap();
+    }
+    current.insert(key.to_string(), value);
+}
+
+fn split_path(path: &str) -> (Vec<&str>, &str) {
+    let mut parts: Vec<&str> = path.split('.').collect();
+    let key = parts.pop().unwrap_or_default();
+    (parts, key)
+}
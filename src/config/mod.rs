@@ -1 +1,3 @@
 pub mod config;
+pub mod migration;
+pub mod secrets;
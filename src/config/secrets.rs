@@ -0,0 +1,205 @@
+use crate::error::error::SecretaryError;
+use age::secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where a secret config value comes from. In `config.toml` this is either a
+/// plain string or a reference such as `{ env = "OPENAI_KEY" }`,
+/// `{ command = "pass show openai" }` or `{ secret = "openai" }` (an entry of the
+/// encrypted secrets file).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SecretSource {
+    Plain(String),
+    Env { env: String },
+    Command { command: String },
+    SecretsFile { secret: String },
+}
+
+impl Default for SecretSource {
+    fn default() -> Self {
+        SecretSource::Plain("".to_string())
+    }
+}
+
+/// A config value that must not be printed or written back to the config file
+/// in resolved form. Serializes as its source.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SecretSource", into = "SecretSource")]
+pub struct Secret {
+    source: SecretSource,
+    value: String,
+}
+
+impl From<SecretSource> for Secret {
+    fn from(source: SecretSource) -> Self {
+        let value = match &source {
+            SecretSource::Plain(value) => value.clone(),
+            _ => "".to_string(),
+        };
+        Secret { source, value }
+    }
+}
+
+impl From<Secret> for SecretSource {
+    fn from(secret: Secret) -> Self {
+        secret.source
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            SecretSource::Plain(_) => write!(f, "Secret(<redacted>)"),
+            source => write!(f, "Secret({:?})", source),
+        }
+    }
+}
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+
+    pub fn source(&self) -> &SecretSource {
+        &self.source
+    }
+
+    /// Replaces the value. Plain secrets are written back to the config file on
+    /// save, secrets-file entries to the secrets file; values from the environment
+    /// or a command only change for this run.
+    pub fn set(&mut self, value: String) {
+        if let SecretSource::Plain(_) = self.source {
+            self.source = SecretSource::Plain(value.clone());
+        }
+        self.value = value;
+    }
+
+    /// Moves the secret into the secrets file under `name`.
+    pub fn store_in(&mut self, secrets_file: &mut SecretsFile, name: &str) {
+        secrets_file.set(name, self.value.clone());
+        self.source = SecretSource::SecretsFile {
+            secret: name.to_string(),
+        };
+    }
+
+    /// Reads the value from its source. Unset variables and unknown secrets-file
+    /// entries resolve to an empty value, which is reported as a missing key.
+    pub fn resolve(&mut self, secrets_file: Option<&SecretsFile>) -> Result<(), SecretaryError> {
+        self.value = match &self.source {
+            SecretSource::Plain(value) => value.clone(),
+            SecretSource::Env { env } => std::env::var(env).unwrap_or_default(),
+            SecretSource::Command { command } => run_secret_command(command)?,
+            SecretSource::SecretsFile { secret } => match secrets_file {
+                Some(secrets_file) => secrets_file.get(secret).unwrap_or_default().to_string(),
+                None => {
+                    return Err(SecretaryError::Config(format!(
+                        "secret '{}' is read from the secrets file, but secrets.file is not set",
+                        secret
+                    )))
+                }
+            },
+        };
+        Ok(())
+    }
+}
+
+fn run_secret_command(command: &str) -> Result<String, SecretaryError> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()?
+    } else {
+        Command::new("sh").args(["-c", command]).output()?
+    };
+    if !output.status.success() {
+        // stderr is reported, stdout never is: it may contain part of the secret
+        return Err(SecretaryError::Config(format!(
+            "secret command '{}' failed ({}): {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8(output.stdout)?.trim_end().to_string())
+}
+
+/// Passphrase-encrypted (age/scrypt) TOML file mapping secret names to values.
+#[derive(Clone)]
+pub struct SecretsFile {
+    path: PathBuf,
+    passphrase: SecretString,
+    secrets: BTreeMap<String, String>,
+}
+
+impl fmt::Debug for SecretsFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretsFile")
+            .field("path", &self.path)
+            .field("secrets", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl SecretsFile {
+    /// Opens and decrypts the file, or starts an empty one if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P, passphrase: String) -> Result<Self, SecretaryError> {
+        let passphrase = SecretString::from(passphrase);
+        let secrets = if path.as_ref().exists() {
+            let encrypted = fs::read(&path)?;
+            let identity = age::scrypt::Identity::new(passphrase.clone());
+            let decrypted = age::decrypt(&identity, &encrypted).map_err(|error| {
+                SecretaryError::Config(format!(
+                    "failed to decrypt secrets file '{}': {}",
+                    path.as_ref().display(),
+                    error
+                ))
+            })?;
+            toml::from_str(&String::from_utf8(decrypted)?)?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(SecretsFile {
+            path: path.as_ref().to_path_buf(),
+            passphrase,
+            secrets,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.secrets.get(name).map(String::as_str)
+    }
+
+    pub fn set(&mut self, name: &str, value: String) {
+        self.secrets.insert(name.to_string(), value);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.secrets.keys()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn save(&self) -> Result<(), SecretaryError> {
+        let recipient = age::scrypt::Recipient::new(self.passphrase.clone());
+        let encrypted = age::encrypt(&recipient, toml::to_string(&self.secrets)?.as_bytes())
+            .map_err(|error| {
+                SecretaryError::Config(format!("failed to encrypt secrets file: {}", error))
+            })?;
+        fs::write(&self.path, encrypted)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+use crate::auth::auth::PendingAuthCode;
+use crate::ledger::ledger::{LedgerEntry, SharedLedger};
+use crate::models::audio_note::DEFAULT_PROMPT_PROFILE;
+use rand::Rng;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::sync::mpsc::UnboundedSender;
+use warp::http::{Response, StatusCode};
+use warp::Filter;
+
+/// Number of ledger entries listed on the dashboard.
+const RECENT_RECORDINGS: usize = 50;
+
+/// Sent by the dashboard when a user asks for a recording to be processed again.
+#[derive(Clone, Debug)]
+pub struct ReprocessRequest {
+    pub id: String,
+    pub profile: String,
+}
+
+pub async fn run_dashboard(
+    port: u16,
+    ledger: SharedLedger,
+    profiles: Vec<String>,
+    reprocess_tx: UnboundedSender<ReprocessRequest>,
+    pending_auth_code: PendingAuthCode,
+) {
+    // Reprocessing costs money, so other sites open in the browser must not be
+    // able to trigger it: the form carries a token only the dashboard knows
+    let token: String = (0..32)
+        .map(|_| format!("{:x}", rand::thread_rng().gen_range(0..16)))
+        .collect();
+    let hosts = [format!("localhost:{}", port), format!("127.0.0.1:{}", port)];
+
+    let index_ledger = ledger.clone();
+    let index_token = token.clone();
+    let index = warp::path::end().and(warp::get()).map(move || {
+        let ledger = index_ledger.lock().unwrap();
+        warp::reply::html(render_index(
+            &ledger.recent(RECENT_RECORDINGS),
+            &profiles,
+            &index_token,
+        ))
+    });
+
+    let audio_ledger = ledger.clone();
+    let audio = warp::path!("audio" / String)
+        .and(warp::get())
+        .then(move |id: String| {
+            // The cached file has no extension, so the content type comes from the Dropbox name.
+            let audio_file = audio_ledger.lock().unwrap().get(&id).and_then(|entry| {
+                let content_type = audio_content_type(Path::new(&entry.audio_file_metadata.name));
+                Some((entry.local_audio_file_path.clone()?, content_type))
+            });
+            async move {
+                let bytes = match audio_file {
+                    Some((path, content_type)) => tokio::fs::read(&path)
+                        .await
+                        .ok()
+                        .map(|bytes| (content_type, bytes)),
+                    None => None,
+                };
+                match bytes {
+                    Some((content_type, bytes)) => Response::builder()
+                        .header("Content-Type", content_type)
+                        .body(bytes),
+                    None => Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(b"Audio file not found".to_vec()),
+                }
+            }
+        });
+
+    let reprocess = warp::path!("recordings" / String / "reprocess")
+        .and(warp::post())
+        .and(warp::header::optional::<String>("host"))
+        .and(warp::body::form::<HashMap<String, String>>())
+        .map(
+            move |id: String, host: Option<String>, form: HashMap<String, String>| {
+                // A page on another site reaching us through DNS rebinding has its own host
+                let trusted_host = host.is_some_and(|host| hosts.contains(&host));
+                if !trusted_host || form.get("token") != Some(&token) {
+                    return Response::builder().status(StatusCode::FORBIDDEN).body(
+                        "Invalid or missing dashboard token. Reload the dashboard and try again."
+                            .to_string(),
+                    );
+                }
+                let profile = form
+                    .get("profile")
+                    .cloned()
+                    .unwrap_or_else(|| DEFAULT_PROMPT_PROFILE.to_string());
+                if reprocess_tx.send(ReprocessRequest { id, profile }).is_err() {
+                    eprintln!("Reprocess request dropped: pipeline is not running");
+                }
+                Response::builder()
+                    .status(StatusCode::SEE_OTHER)
+                    .header("Location", "/")
+                    .body("".to_string())
+            },
+        );
+
+    // Receives the code for a headless Dropbox authorization
+    let auth_code = warp::path!("auth" / "code")
+        .and(warp::post())
+        .and(warp::body::form::<HashMap<String, String>>())
+        .map(move |form: HashMap<String, String>| {
+            let code = form.get("code").map(|code| code.trim().to_string());
+            let waiting = pending_auth_code.lock().unwrap().take();
+            match (code, waiting) {
+                (Some(code), Some(tx)) if !code.is_empty() => {
+                    let _ = tx.send(code);
+                    warp::reply::with_status("Authorization code received.", StatusCode::OK)
+                }
+                (_, waiting) => {
+                    // Put the sender back so a corrected request can still complete
+                    *pending_auth_code.lock().unwrap() = waiting;
+                    warp::reply::with_status(
+                        "No authorization pending or missing 'code'.",
+                        StatusCode::BAD_REQUEST,
+                    )
+                }
+            }
+        });
+
+    println!("Dashboard running on http://localhost:{}/", port);
+    warp::serve(index.or(audio).or(reprocess).or(auth_code))
+        .run(([127, 0, 0, 1], port))
+        .await;
+}
+
+fn render_index(entries: &[&LedgerEntry], profiles: &[String], token: &str) -> String {
+    let rows: String = entries
+        .iter()
+        .map(|entry| render_row(entry, profiles, token))
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta http-equiv="refresh" content="30">
+<title>Secretary</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border-bottom: 1px solid #ddd; padding: 0.5em; text-align: left; vertical-align: middle; }}
+.failed {{ color: #b00020; }}
+</style>
+</head>
+<body>
+<h1>Recent recordings</h1>
+<table>
+<tr><th>Recording</th><th>Status</th><th>Duration</th><th>Cost</th><th>Note</th><th>Audio</th><th>Reprocess</th></tr>
+{}
+</table>
+</body>
+</html>"#,
+        rows
+    )
+}
+
+fn render_row(entry: &LedgerEntry, profiles: &[String], token: &str) -> String {
+    let id = escape_html(&entry.audio_file_metadata.id);
+    let status = escape_html(&entry.status.to_string());
+    let status_class = if status.starts_with("Failed") {
+        "failed"
+    } else {
+        ""
+    };
+
+    let duration = entry
+        .duration_seconds
+        .map(format_duration)
+        .unwrap_or_else(|| "-".to_string());
+
+    let note_link = match &entry.note_path {
+        Some(path) => {
+            match Url::parse_with_params("obsidian://open", &[("path", path.to_string_lossy())]) {
+                Ok(url) => format!(
+                    r#"<a href="{}">{}</a>"#,
+                    escape_html(url.as_str()),
+                    escape_html(&file_stem(path))
+                ),
+                Err(_) => escape_html(&file_stem(path)),
+            }
+        }
+        None => "-".to_string(),
+    };
+
+    // The recording may have been removed from the cache since
+    let audio_player = match &entry.local_audio_file_path {
+        Some(path) if path.exists() => format!(
+            r#"<audio controls preload="none" src="/audio/{}"></audio>"#,
+            id
+        ),
+        _ => "-".to_string(),
+    };
+
+    let options: String = profiles
+        .iter()
+        .map(|profile| {
+            let selected = if *profile == entry.prompt_profile {
+                " selected"
+            } else {
+                ""
+            };
+            let profile = escape_html(profile);
+            format!(r#"<option value="{0}"{1}>{0}</option>"#, profile, selected)
+        })
+        .collect();
+
+    format!(
+        r#"<tr><td>{}</td><td class="{}">{}</td><td>{}</td><td>${:.4}</td><td>{}</td><td>{}</td><td><form method="post" action="/recordings/{}/reprocess"><input type="hidden" name="token" value="{}"><select name="profile">{}</select> <button type="submit">Reprocess</button></form></td></tr>
+"#,
+        escape_html(&entry.audio_file_metadata.name),
+        status_class,
+        status,
+        duration,
+        entry.cost,
+        note_link,
+        audio_player,
+        id,
+        token,
+        options
+    )
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn audio_content_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(std::ffi::OsStr::to_str)
+        .map(str::to_lowercase)
+        .as_deref()
+    {
+        Some("mp3") | Some("mpga") | Some("mpeg") => "audio/mpeg",
+        Some("m4a") | Some("mp4") => "audio/mp4",
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        Some("ogg") | Some("oga") => "audio/ogg",
+        Some("webm") => "audio/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -1,40 +1,166 @@
 use crate::config::config::Config;
+use crate::error::error::SecretaryError;
+use crate::http::http::HttpClient;
 use crate::models::dropbox_file_metadata::DropboxFileMetadata;
-use reqwest::Client;
+use reqwest::header::RANGE;
+use reqwest::{RequestBuilder, Response, StatusCode};
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::collections::HashSet;
-use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How often an interrupted download is resumed before giving up.
+const DOWNLOAD_RESUME_ATTEMPTS: u32 = 3;
+
+/// Dropbox hashes files in blocks of this size, see `dropbox_content_hash`.
+const CONTENT_HASH_BLOCK_SIZE: usize = 4 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct DropboxClient {
-    pub client: Client,
-    pub access_token: String,
+    pub http: HttpClient,
+    access_token: Arc<RwLock<String>>,
+    pub refresh_token: String,
     pub dropbox_client_id: String,
     pub dropbox_client_secret: String,
     pub audio_path: String,
+    pub include_subfolders: bool,
 }
 
 pub type Audio = Vec<u8>;
 
+/// One page of a folder listing. Entries are files, folders or deleted items,
+/// told apart by their `.tag`.
 #[derive(Debug, Deserialize)]
 pub struct DropboxListFolderResponse {
-    entries: Vec<DropboxFileMetadata>,
+    entries: Vec<Value>,
+    cursor: String,
+    has_more: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DropboxSharedLink {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DropboxSharedLinks {
+    links: Vec<DropboxSharedLink>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DropboxTokenResponse {
+    access_token: String,
 }
 
 impl DropboxClient {
-    pub fn new(config: &Config) -> Self {
-        let access_token = config.dropbox_access_token.clone();
-        let dropbox_client_id = config.dropbox_client_id.clone();
-        let dropbox_client_secret = config.dropbox_client_secret.clone();
-        let client = Client::new();
-        let audio_path = config.dropbox_audio_path.clone();
+    pub fn new(config: &Config, http: HttpClient) -> Self {
+        let access_token = Arc::new(RwLock::new(
+            config.dropbox.access_token.expose().to_string(),
+        ));
+        let refresh_token = config.dropbox.refresh_token.expose().to_string();
+        let dropbox_client_id = config.dropbox.client_id.clone();
+        let dropbox_client_secret = config.dropbox.client_secret.expose().to_string();
+        let audio_path = config.dropbox.audio_path.clone();
+        let include_subfolders = config.dropbox.include_subfolders;
         DropboxClient {
-            client,
+            http,
             access_token,
+            refresh_token,
             dropbox_client_id,
             dropbox_client_secret,
             audio_path,
+            include_subfolders,
+        }
+    }
+
+    /// The current access token. Clones of the client share it, so a refresh made
+    /// by one is seen by all.
+    pub fn access_token(&self) -> String {
+        self.access_token.read().unwrap().clone()
+    }
+
+    pub fn set_access_token(&self, access_token: String) {
+        *self.access_token.write().unwrap() = access_token;
+    }
+
+    /// Exchanges the stored refresh token for a new short-lived access token.
+    pub async fn refresh_access_token(&self) -> Result<(), SecretaryError> {
+        if self.refresh_token.is_empty() {
+            return Err(SecretaryError::AuthExpired {
+                service: "Dropbox",
+                message: "no refresh token available; authorization is required".to_string(),
+            });
+        }
+
+        // PKCE clients have no secret; Dropbox then only needs the client id.
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", self.refresh_token.as_str()),
+            ("client_id", self.dropbox_client_id.as_str()),
+        ];
+        if !self.dropbox_client_secret.is_empty() {
+            form.push(("client_secret", self.dropbox_client_secret.as_str()));
+        }
+
+        let response = self
+            .http
+            .send(|| {
+                self.http
+                    .client
+                    .post("https://api.dropboxapi.com/oauth2/token")
+                    .form(&form)
+            })
+            .await?;
+
+        if response.status().is_success() {
+            let token: DropboxTokenResponse = response.json().await?;
+            self.set_access_token(token.access_token);
+            println!("Dropbox access token refreshed.");
+            Ok(())
+        } else {
+            // A rejected refresh token means the user has to authorize again
+            match SecretaryError::from_response("Dropbox", response).await {
+                SecretaryError::Http {
+                    status: 400,
+                    message,
+                    ..
+                } => Err(SecretaryError::AuthExpired {
+                    service: "Dropbox",
+                    message,
+                }),
+                error => Err(error),
+            }
+        }
+    }
+
+    /// Sends the request built by `build` with the current access token. If Dropbox
+    /// reports the token as expired, refreshes it and sends the request once more.
+    async fn send_authorized<F>(&self, build: F) -> Result<Response, SecretaryError>
+    where
+        F: Fn(&str) -> RequestBuilder,
+    {
+        let access_token = self.access_token();
+        let response = self.http.send(|| build(&access_token)).await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let error_body = response.text().await?;
+        if error_body.contains("expired_access_token") && !self.refresh_token.is_empty() {
+            self.refresh_access_token().await?;
+            let access_token = self.access_token();
+            self.http.send(|| build(&access_token)).await
+        } else {
+            Err(SecretaryError::from_status(
+                "Dropbox",
+                status,
+                None,
+                &error_body,
+            ))
         }
     }
 
@@ -53,66 +179,195 @@ impl DropboxClient {
         exts
     }
 
-    pub async fn list_files(&self) -> Result<Vec<DropboxFileMetadata>, Box<dyn std::error::Error>> {
-        let url = "https://api.dropboxapi.com/2/files/list_folder";
-        let body = json!({
+    /// Lists the files in `audio_path`, and with `include_subfolders` in the
+    /// folders below it, following the listing across pages.
+    pub async fn list_files(&self) -> Result<Vec<DropboxFileMetadata>, SecretaryError> {
+        let mut url = "https://api.dropboxapi.com/2/files/list_folder";
+        let mut body = json!({
             "path": self.audio_path,
-            "recursive": false,
+            "recursive": self.include_subfolders,
             "include_media_info": false,
             "include_deleted": false,
             "include_has_explicit_shared_members": false
         });
 
+        let mut files = Vec::new();
+        loop {
+            let response = self
+                .send_authorized(|token| {
+                    self.http
+                        .client
+                        .post(url)
+                        .bearer_auth(token)
+                        .header("Content-Type", "application/json")
+                        .body(body.to_string())
+                })
+                .await?;
+            if !response.status().is_success() {
+                return Err(SecretaryError::from_response("Dropbox", response).await);
+            }
+
+            let page: DropboxListFolderResponse = response.json().await?;
+            for entry in page.entries {
+                if entry[".tag"] == "file" {
+                    files.push(serde_json::from_value(entry)?);
+                }
+            }
+            if !page.has_more {
+                break;
+            }
+            url = "https://api.dropboxapi.com/2/files/list_folder/continue";
+            body = json!({ "cursor": page.cursor });
+        }
+
+        println!("Listed files: {:?}", files);
+        Ok(files)
+    }
+
+    /// Returns a shared link to `file_path`, creating one unless it already has one.
+    pub async fn shared_link(&self, file_path: &str) -> Result<String, SecretaryError> {
+        let url = "https://api.dropboxapi.com/2/sharing/create_shared_link_with_settings";
+        let body = json!({ "path": file_path });
         let response = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header("Content-Type", "application/json")
-            .body(body.to_string())
-            .send()
+            .send_authorized(|token| self.http.client.post(url).bearer_auth(token).json(&body))
             .await?;
-
         if response.status().is_success() {
-            let response_body: DropboxListFolderResponse = response.json().await?;
-            println!("Listed files: {:?}", response_body.entries);
-            Ok(response_body.entries)
-        } else {
-            Err(format!("Failed to list files: {}", response.text().await?).into())
+            let link: DropboxSharedLink = response.json().await?;
+            return Ok(link.url);
         }
+
+        let error = SecretaryError::from_response("Dropbox", response).await;
+        if !error.to_string().contains("shared_link_already_exists") {
+            return Err(error);
+        }
+        let url = "https://api.dropboxapi.com/2/sharing/list_shared_links";
+        let body = json!({ "path": file_path, "direct_only": true });
+        let response = self
+            .send_authorized(|token| self.http.client.post(url).bearer_auth(token).json(&body))
+            .await?;
+        if !response.status().is_success() {
+            return Err(SecretaryError::from_response("Dropbox", response).await);
+        }
+        let links: DropboxSharedLinks = response.json().await?;
+        links
+            .links
+            .into_iter()
+            .next()
+            .map(|link| link.url)
+            .ok_or_else(|| {
+                SecretaryError::Decode(format!("no shared link returned for '{}'", file_path))
+            })
     }
 
-    pub async fn download_file(&self, file_path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    /// Streams `file_path` to `destination` without holding it in memory. The
+    /// body is written to `<destination>.part` first; an interrupted download is
+    /// resumed from there with a range request. When `content_hash` is given, the
+    /// finished file must match it; if it doesn't, the download starts over once.
+    pub async fn download_file(
+        &self,
+        file_path: &str,
+        destination: &Path,
+        content_hash: Option<&str>,
+    ) -> Result<(), SecretaryError> {
+        let mut part_path = destination.as_os_str().to_owned();
+        part_path.push(".part");
+        let part_path = PathBuf::from(part_path);
+
+        let mut restarted = false;
+        loop {
+            let mut attempt = 0;
+            loop {
+                match self.download_part(file_path, &part_path).await {
+                    Ok(()) => break,
+                    // The connection dropped mid-body: resume from what we have
+                    Err(SecretaryError::Network(error)) if attempt < DOWNLOAD_RESUME_ATTEMPTS => {
+                        attempt += 1;
+                        eprintln!(
+                            "Download of '{}' interrupted ({}), resuming.",
+                            file_path, error
+                        );
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+
+            let Some(expected) = content_hash else {
+                break;
+            };
+            let actual = dropbox_content_hash(&part_path).await?;
+            if actual == expected {
+                break;
+            }
+            // A resumed download may have been stitched from two versions of
+            // the file, so start from scratch rather than resume
+            tokio::fs::remove_file(&part_path).await?;
+            let error = SecretaryError::Integrity(format!(
+                "content hash mismatch for '{}': expected {}, got {}",
+                file_path, expected, actual
+            ));
+            if restarted {
+                return Err(error);
+            }
+            eprintln!("{}, downloading it again.", error);
+            restarted = true;
+        }
+        tokio::fs::rename(&part_path, destination).await?;
+        println!("Downloaded file: {}", file_path);
+        Ok(())
+    }
+
+    /// Appends the rest of `file_path` to `part_path`, or downloads it from the
+    /// start if the server ignores the range.
+    async fn download_part(&self, file_path: &str, part_path: &Path) -> Result<(), SecretaryError> {
         let url = "https://content.dropboxapi.com/2/files/download";
-        let arg = json!({ "path": file_path });
+        let arg = serde_json::to_string(&json!({ "path": file_path }))?;
+        let offset = match tokio::fs::metadata(part_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
 
-        let response = self
-            .client
-            .post(url)
-            .bearer_auth(&self.access_token)
-            .header("Dropbox-API-Arg", serde_json::to_string(&arg)?)
-            .send()
+        let mut response = self
+            .send_authorized(|token| {
+                let request = self
+                    .http
+                    .client
+                    .post(url)
+                    .bearer_auth(token)
+                    .header("Dropbox-API-Arg", arg.as_str());
+                if offset > 0 {
+                    request.header(RANGE, format!("bytes={}-", offset))
+                } else {
+                    request
+                }
+            })
             .await?;
 
-        if response.status().is_success() {
-            let file_content = response.bytes().await?;
-            println!("Downloaded file: {}", file_path);
-            Ok(file_content.to_vec())
-        } else {
-            let error_body = response.text().await?;
-            Err(Box::new(tokio::io::Error::new(
-                tokio::io::ErrorKind::Other,
-                format!(
-                    "Failed to download file: '{}'. Server responded with: '{}'",
-                    file_path, error_body
-                ),
-            )))
+        let append = match response.status() {
+            StatusCode::PARTIAL_CONTENT => true,
+            // The partial file already holds the whole file
+            StatusCode::RANGE_NOT_SATISFIABLE => return Ok(()),
+            status if status.is_success() => false,
+            _ => return Err(SecretaryError::from_response("Dropbox", response).await),
+        };
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(part_path)
+            .await?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
         }
+        file.flush().await?;
+        Ok(())
     }
 
     pub async fn update_file(
         &self,
         updated_file_metadata: &DropboxFileMetadata,
-    ) -> Result<DropboxFileMetadata, Box<dyn std::error::Error>> {
+    ) -> Result<DropboxFileMetadata, SecretaryError> {
         let url = "https://api.dropboxapi.com/2/files/update";
 
         let body = json!({
@@ -122,12 +377,14 @@ impl DropboxClient {
         });
 
         let response = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.access_token))
-            .header("Content-Type", "application/json")
-            .header("Dropbox-API-Arg", body.to_string())
-            .send()
+            .send_authorized(|token| {
+                self.http
+                    .client
+                    .post(url)
+                    .bearer_auth(token)
+                    .header("Content-Type", "application/json")
+                    .header("Dropbox-API-Arg", body.to_string())
+            })
             .await?;
 
         match response.status().is_success() {
@@ -135,10 +392,34 @@ impl DropboxClient {
                 let confirmation: DropboxFileMetadata = response.json().await?;
                 Ok(confirmation)
             }
-            false => Err(Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to update file: {}", response.text().await?),
-            ))),
+            false => Err(SecretaryError::from_response("Dropbox", response).await),
+        }
+    }
+}
+
+/// Computes Dropbox's content hash of a local file: the SHA-256 of the
+/// concatenated SHA-256 hashes of each 4 MiB block, as lowercase hex.
+pub async fn dropbox_content_hash(path: &Path) -> Result<String, SecretaryError> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut overall = Sha256::new();
+    let mut block = vec![0u8; CONTENT_HASH_BLOCK_SIZE];
+    loop {
+        // Fill a whole block; `read` may return less than asked for
+        let mut filled = 0;
+        while filled < block.len() {
+            let read = file.read(&mut block[filled..]).await?;
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        if filled == 0 {
+            break;
+        }
+        overall.update(Sha256::digest(&block[..filled]));
+        if filled < block.len() {
+            break;
         }
     }
+    Ok(format!("{:x}", overall.finalize()))
 }
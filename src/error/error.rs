@@ -0,0 +1,235 @@
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Every error Secretary reports. The variants tell the pipeline what to do with
+/// a failed recording: retry it later, re-authorize, or give up on it.
+#[derive(Debug, Error)]
+pub enum SecretaryError {
+    /// The request never got a response (DNS, connection, timeout).
+    #[error("Network error: {0}")]
+    Network(reqwest::Error),
+    /// The request couldn't be built or sent as given, e.g. an invalid URL or
+    /// header value; sending it again won't help.
+    #[error("Invalid request: {0}")]
+    Request(reqwest::Error),
+    /// The API answered with an error status; `message` is taken from its error body.
+    #[error("{service} API error ({status}): {message}")]
+    Http {
+        service: &'static str,
+        status: u16,
+        message: String,
+    },
+    #[error("{service} rate limit reached{}", retry_after_suffix(.retry_after))]
+    RateLimited {
+        service: &'static str,
+        retry_after: Option<Duration>,
+    },
+    /// The access token expired or was revoked; authorizing again fixes it.
+    #[error("{service} authorization expired: {message}")]
+    AuthExpired {
+        service: &'static str,
+        message: String,
+    },
+    #[error("{service} quota exceeded: {message}")]
+    QuotaExceeded {
+        service: &'static str,
+        message: String,
+    },
+    /// A response or file that doesn't have the expected format.
+    #[error("Failed to decode: {0}")]
+    Decode(String),
+    /// A download that doesn't match its checksum, e.g. after a corrupted transfer.
+    #[error("Integrity check failed: {0}")]
+    Integrity(String),
+    #[error("Filesystem error: {0}")]
+    Filesystem(#[from] std::io::Error),
+    #[error("Configuration error: {0}")]
+    Config(String),
+    /// Authorization failed or was denied (as opposed to an expired token).
+    #[error("Authorization failed: {0}")]
+    Auth(String),
+    /// Invalid command line arguments or command.
+    #[error("{0}")]
+    Usage(String),
+    /// A bug, such as a panic while processing a recording.
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+fn retry_after_suffix(retry_after: &Option<Duration>) -> String {
+    match retry_after {
+        Some(retry_after) => format!(", retry after {}s", retry_after.as_secs()),
+        None => "".to_string(),
+    }
+}
+
+impl SecretaryError {
+    /// Reads the error body of a failed response and classifies it.
+    pub async fn from_response(service: &'static str, response: Response) -> SecretaryError {
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<u64>().ok())
+            .map(Duration::from_secs);
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(error) => return error.into(),
+        };
+        SecretaryError::from_status(service, status, retry_after, &body)
+    }
+
+    pub fn from_status(
+        service: &'static str,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> SecretaryError {
+        let message = api_error_message(body);
+        // Dropbox states the delay in the error body rather than a header
+        let retry_after = retry_after.or_else(|| {
+            serde_json::from_str::<Value>(body)
+                .ok()?
+                .pointer("/error/retry_after")?
+                .as_u64()
+                .map(Duration::from_secs)
+        });
+        match status {
+            // OpenAI reports an exhausted quota as a 429 too, but retrying won't help
+            StatusCode::TOO_MANY_REQUESTS if body.contains("insufficient_quota") => {
+                SecretaryError::QuotaExceeded { service, message }
+            }
+            StatusCode::TOO_MANY_REQUESTS => SecretaryError::RateLimited {
+                service,
+                retry_after,
+            },
+            // A busy server that says when to come back is a rate limit too
+            StatusCode::SERVICE_UNAVAILABLE if retry_after.is_some() => {
+                SecretaryError::RateLimited {
+                    service,
+                    retry_after,
+                }
+            }
+            // Only an expired or revoked Dropbox token is fixed by authorizing again
+            StatusCode::UNAUTHORIZED
+                if service == "Dropbox"
+                    && (body.contains("expired_access_token")
+                        || body.contains("invalid_access_token")) =>
+            {
+                SecretaryError::AuthExpired { service, message }
+            }
+            StatusCode::UNAUTHORIZED => {
+                SecretaryError::Auth(format!("{} rejected the credentials: {}", service, message))
+            }
+            _ => SecretaryError::Http {
+                service,
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+
+    /// Whether the same request may succeed when sent again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SecretaryError::Network(_)
+            | SecretaryError::RateLimited { .. }
+            | SecretaryError::Integrity(_) => true,
+            SecretaryError::Http { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// How long the API asked us to wait before retrying, if it said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            SecretaryError::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Names the config `key` whose value the API rejected, so the user knows
+    /// what to fix. Other errors are returned as they are.
+    pub fn for_key(self, key: &str) -> SecretaryError {
+        match self {
+            SecretaryError::Auth(message) => {
+                SecretaryError::Config(format!("{} was rejected: {}", key, message))
+            }
+            error => error,
+        }
+    }
+
+    pub fn requires_reauth(&self) -> bool {
+        matches!(self, SecretaryError::AuthExpired { .. })
+    }
+}
+
+/// The human-readable part of an API error body: OpenAI's `error.message`,
+/// Dropbox's `error_summary`, or the body itself.
+fn api_error_message(body: &str) -> String {
+    if let Ok(json) = serde_json::from_str::<Value>(body) {
+        if let Some(message) = json["error"]["message"].as_str() {
+            return message.to_string();
+        }
+        if let Some(summary) = json["error_summary"].as_str() {
+            return summary.to_string();
+        }
+    }
+    body.trim().to_string()
+}
+
+impl From<reqwest::Error> for SecretaryError {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            SecretaryError::Decode(error.to_string())
+        } else if error.is_builder() || (error.is_request() && !is_transient(&error)) {
+            SecretaryError::Request(error)
+        } else {
+            SecretaryError::Network(error)
+        }
+    }
+}
+
+/// Whether a failed request may succeed when sent again: it failed to connect,
+/// timed out, or the connection broke while it was being sent.
+fn is_transient(error: &reqwest::Error) -> bool {
+    if error.is_connect() || error.is_timeout() {
+        return true;
+    }
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        if cause.is::<std::io::Error>() {
+            return true;
+        }
+        source = cause.source();
+    }
+    false
+}
+
+impl From<serde_json::Error> for SecretaryError {
+    fn from(error: serde_json::Error) -> Self {
+        SecretaryError::Decode(error.to_string())
+    }
+}
+
+impl From<std::string::FromUtf8Error> for SecretaryError {
+    fn from(error: std::string::FromUtf8Error) -> Self {
+        SecretaryError::Decode(error.to_string())
+    }
+}
+
+impl From<toml::de::Error> for SecretaryError {
+    fn from(error: toml::de::Error) -> Self {
+        SecretaryError::Config(error.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for SecretaryError {
+    fn from(error: toml::ser::Error) -> Self {
+        SecretaryError::Config(error.to_string())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::config::config::HttpConfig;
+use crate::error::error::SecretaryError;
+use rand::Rng;
+use reqwest::{Client, RequestBuilder, Response};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{sleep, sleep_until, Instant};
+
+/// Limits requests to one provider: at most `max_concurrent` in flight, started
+/// no closer together than `60 / requests_per_minute` seconds.
+struct RateLimiter {
+    permits: Semaphore,
+    interval: Duration,
+    next_start: Mutex<Instant>,
+}
+
+/// HTTP client for one provider (Dropbox or OpenAI) with timeouts, rate limits,
+/// and retries with exponential backoff. Clones share the same limits.
+#[derive(Clone)]
+pub struct HttpClient {
+    pub client: Client,
+    service: &'static str,
+    limiter: Arc<RateLimiter>,
+    max_retries: u32,
+    backoff_base: Duration,
+    backoff_max: Duration,
+}
+
+/// The HTTP clients shared by all API clients, one per provider.
+#[derive(Clone)]
+pub struct HttpClients {
+    pub dropbox: HttpClient,
+    pub openai: HttpClient,
+}
+
+impl HttpClients {
+    pub fn new(config: &HttpConfig) -> Result<Self, SecretaryError> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .read_timeout(Duration::from_secs(config.read_timeout_secs))
+            .build()?;
+        Ok(HttpClients {
+            dropbox: HttpClient::new(
+                client.clone(),
+                "Dropbox",
+                config.dropbox_max_concurrent,
+                config.dropbox_requests_per_minute,
+                config,
+            ),
+            openai: HttpClient::new(
+                client,
+                "OpenAI",
+                config.openai_max_concurrent,
+                config.openai_requests_per_minute,
+                config,
+            ),
+        })
+    }
+}
+
+impl HttpClient {
+    fn new(
+        client: Client,
+        service: &'static str,
+        max_concurrent: usize,
+        requests_per_minute: u32,
+        config: &HttpConfig,
+    ) -> Self {
+        let limiter = RateLimiter {
+            permits: Semaphore::new(max_concurrent.max(1)),
+            interval: Duration::from_secs(60) / requests_per_minute.max(1),
+            next_start: Mutex::new(Instant::now()),
+        };
+        HttpClient {
+            client,
+            service,
+            limiter: Arc::new(limiter),
+            max_retries: config.max_retries,
+            backoff_base: Duration::from_millis(config.backoff_base_ms),
+            backoff_max: Duration::from_secs(config.backoff_max_secs),
+        }
+    }
+
+    /// Sends the request built by `build`, within the provider's limits. Network
+    /// errors, rate limits and server errors are retried after the delay the API
+    /// asked for, or an exponential backoff. Other responses, successful or not,
+    /// are returned as they are.
+    pub async fn send<F>(&self, build: F) -> Result<Response, SecretaryError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = {
+                let _permit = self.limiter.acquire().await;
+                build().send().await
+            };
+            let error = match result {
+                Ok(response)
+                    if !(response.status().is_server_error()
+                        || response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS) =>
+                {
+                    return Ok(response)
+                }
+                Ok(response) => SecretaryError::from_response(self.service, response).await,
+                Err(error) => error.into(),
+            };
+
+            if !error.is_retryable() || attempt >= self.max_retries {
+                return Err(error);
+            }
+            let delay = error.retry_after().unwrap_or_else(|| self.backoff(attempt));
+            attempt += 1;
+            eprintln!(
+                "{}. Retrying in {:.1}s (attempt {} of {}).",
+                error,
+                delay.as_secs_f64(),
+                attempt,
+                self.max_retries
+            );
+            sleep(delay).await;
+        }
+    }
+
+    /// Exponential backoff with jitter: a random delay between half and all of
+    /// `backoff_base * 2^attempt`, capped at `backoff_max`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff_base
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.backoff_max);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+impl RateLimiter {
+    async fn acquire(&self) -> tokio::sync::SemaphorePermit<'_> {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("rate limiter semaphore is never closed");
+        let start = {
+            let mut next_start = self.next_start.lock().await;
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + self.interval;
+            start
+        };
+        sleep_until(start).await;
+        permit
+    }
+}
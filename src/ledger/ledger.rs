@@ -0,0 +1,169 @@
+use crate::error::error::SecretaryError;
+use crate::models::audio_note::AudioNote;
+use crate::models::dropbox_file_metadata::DropboxFileMetadata;
+use crate::models::recording_status::RecordingStatus;
+use crate::models::usage::Usage;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub type SharedLedger = Arc<Mutex<Ledger>>;
+
+/// One processed (or in-flight) recording, as shown on the dashboard.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LedgerEntry {
+    pub audio_file_metadata: DropboxFileMetadata,
+    pub status: RecordingStatus,
+    pub duration_seconds: Option<f64>,
+    pub cost: f64,
+    #[serde(default)]
+    pub usage: Usage,
+    pub prompt_profile: String,
+    pub note_path: Option<PathBuf>,
+    pub local_audio_file_path: Option<PathBuf>,
+    pub updated_at: String,
+}
+
+/// What one run of the pipeline consumed. Runs are kept even when the recording
+/// is processed again, so spending adds up per period.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UsageRecord {
+    pub id: String,
+    pub name: String,
+    pub model: String,
+    pub usage: Usage,
+    pub cost: f64,
+    pub recorded_at: String,
+}
+
+/// Persistent record of every recording Secretary has seen, keyed by Dropbox file id.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Ledger {
+    #[serde(skip)]
+    path: PathBuf,
+    entries: Vec<LedgerEntry>,
+    #[serde(default)]
+    usage_records: Vec<UsageRecord>,
+    /// Keep changes in memory only, for dry runs.
+    #[serde(skip)]
+    read_only: bool,
+}
+
+impl Ledger {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SecretaryError> {
+        let mut ledger = if path.as_ref().exists() {
+            let contents = fs::read_to_string(&path)?;
+            serde_json::from_str(&contents)?
+        } else {
+            Ledger::default()
+        };
+        ledger.path = path.as_ref().to_path_buf();
+        Ok(ledger)
+    }
+
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+    }
+
+    pub fn save(&self) -> Result<(), SecretaryError> {
+        if self.read_only {
+            return Ok(());
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, id: &str) -> Option<&LedgerEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.audio_file_metadata.id == id)
+    }
+
+    /// Entries sorted with the most recently updated first.
+    pub fn recent(&self, limit: usize) -> Vec<&LedgerEntry> {
+        let mut entries: Vec<&LedgerEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        entries.truncate(limit);
+        entries
+    }
+
+    pub fn usage_records(&self) -> &[UsageRecord] {
+        &self.usage_records
+    }
+
+    /// Adds what the latest run for `audio_note` consumed to the usage history.
+    /// A dry run changes nothing on disk, so its cost is only printed.
+    pub fn record_usage(&mut self, audio_note: &AudioNote) -> Result<(), SecretaryError> {
+        if self.read_only {
+            println!(
+                "Dry run of {} cost ${:.4}; not recorded in the usage history.",
+                audio_note.audio_file_metadata.name, audio_note.cost
+            );
+        }
+        self.usage_records.push(UsageRecord {
+            id: audio_note.audio_file_metadata.id.clone(),
+            name: audio_note.audio_file_metadata.name.clone(),
+            model: audio_note.model.clone(),
+            usage: audio_note.usage,
+            cost: audio_note.cost,
+            recorded_at: Utc::now().to_rfc3339(),
+        });
+        self.save()
+    }
+
+    /// Total cost of the runs in the current calendar month, in local time.
+    pub fn cost_this_month(&self) -> f64 {
+        let month = Local::now().format("%Y-%m").to_string();
+        self.usage_records
+            .iter()
+            .filter(|record| local_period(&record.recorded_at, "%Y-%m") == month)
+            .map(|record| record.cost)
+            .sum()
+    }
+
+    /// Upserts the entry for `audio_note` and writes the ledger back to disk.
+    pub fn record(&mut self, audio_note: &AudioNote) -> Result<(), SecretaryError> {
+        let entry = LedgerEntry {
+            audio_file_metadata: audio_note.audio_file_metadata.clone(),
+            status: audio_note.status.clone(),
+            duration_seconds: audio_note.duration_seconds,
+            cost: audio_note.cost,
+            usage: audio_note.usage,
+            prompt_profile: audio_note.prompt_profile.clone(),
+            note_path: non_empty_path(&audio_note.note_path),
+            local_audio_file_path: non_empty_path(&audio_note.local_audio_file_path),
+            updated_at: Utc::now().to_rfc3339(),
+        };
+
+        match self
+            .entries
+            .iter_mut()
+            .find(|existing| existing.audio_file_metadata.id == entry.audio_file_metadata.id)
+        {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+        self.save()
+    }
+}
+
+/// `timestamp` (RFC 3339) in local time, formatted with `format`, e.g. `%Y-%m`.
+pub fn local_period(timestamp: &str, format: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|time| time.with_timezone(&Local).format(format).to_string())
+        .unwrap_or_default()
+}
+
+fn non_empty_path(path: &Path) -> Option<PathBuf> {
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path.to_path_buf())
+    }
+}
@@ -1,8 +1,19 @@
+#![allow(clippy::module_inception)]
+
 pub mod auth;
+pub mod cache;
+pub mod cli;
 pub mod config;
+pub mod dashboard;
 pub mod dropbox;
+pub mod error;
+pub mod http;
+pub mod ledger;
 pub mod models;
+pub mod obsidian;
 pub mod openai;
+pub mod pipeline;
+pub mod prompts;
 pub mod secretary;
 pub mod utils;
 pub mod whisper;
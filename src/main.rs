@@ -1,35 +1,89 @@
-// TODO: Add error handling throughout main() to gracefully handle issues. [[4]](https://poe.com/citation?message_id=175047583702&citation=4)
 // TODO: Use more descriptive variable names than just "model". [[4]](https://poe.com/citation?message_id=175047583702&citation=4)
 
 // TODO: Add unit tests for key components like config parsing, Dropbox operations, etc.
 // TODO: Integrate a logging framework for better troubleshooting.
-// TODO: Implement proper secrets management rather than hardcoding in the config. [[3]](https://poe.com/citation?message_id=175047583702&citation=3)
 // TODO: Document the main types and functions with doc comments.
 // TODO: Set up CI to automatically run tests, linting, and formatting checks.
 
 use secretary::auth::auth::initialize;
+use secretary::cli::cli::{Cli, USAGE};
+use secretary::cli::commands;
 use secretary::config::config::Config;
+use secretary::dashboard::dashboard::run_dashboard;
+use secretary::error::error::SecretaryError;
 use secretary::secretary::Secretary;
+use tokio::sync::mpsc;
 use tokio::time::{sleep, Duration};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
+    if let Err(error) = run().await {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+async fn run() -> Result<(), SecretaryError> {
+    let cli = Cli::from_env()?;
+    if cli.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+    if !cli.command.is_empty() {
+        return commands::run(&cli).await;
+    }
+
     // Load configuration
-    let config = Config::from_file("config.toml")?;
-    let mut secretary = Secretary::new(config);
+    let config = Config::load(&cli.config_path, &cli.overrides)?;
+    let mut secretary = Secretary::new(config)?;
+
+    // Serve the dashboard alongside the processing loop. It starts first so a
+    // headless authorization can receive its code through it.
+    let (reprocess_tx, mut reprocess_rx) = mpsc::unbounded_channel();
+    tokio::spawn(run_dashboard(
+        secretary.config.dashboard.port,
+        secretary.ledger.clone(),
+        secretary.prompts.profiles(),
+        reprocess_tx,
+        secretary.pending_auth_code.clone(),
+    ));
+
     initialize(&mut secretary).await?;
+    if secretary.config.dry_run {
+        return secretary.print_plan().await;
+    }
 
     // Continuous loop to process new files
     loop {
+        while let Ok(request) = reprocess_rx.try_recv() {
+            if let Err(error) = secretary.queue_reprocess(request) {
+                eprintln!("Failed to queue reprocess request: {}", error);
+            }
+        }
+
         println!("Checking for new files...");
-        secretary.update_audio_notes().await?;
-        secretary.download_audio_files().await?;
-        secretary.transcribe_audio_files().await?;
-        secretary.process_transcriptions().await?;
-        secretary.clean_notes().await?;
-        secretary.save_notes().await?;
+        if let Err(error) = process_new_files(&mut secretary).await {
+            if error.requires_reauth() {
+                eprintln!("{}. Authorizing again.", error);
+                initialize(&mut secretary).await?;
+            } else if error.is_retryable() {
+                eprintln!("{}. Retrying on the next check.", error);
+                if let Some(retry_after) = error.retry_after() {
+                    sleep(retry_after).await;
+                    continue;
+                }
+            } else {
+                return Err(error);
+            }
+        }
 
         // Wait for a specified duration before checking again
-        sleep(Duration::from_secs(60)).await; // Check every 60 seconds
+        sleep(Duration::from_secs(secretary.config.poll_interval_secs)).await;
     }
 }
+
+async fn process_new_files(secretary: &mut Secretary) -> Result<(), SecretaryError> {
+    secretary.update_audio_notes().await?;
+    secretary.process_audio_notes().await?;
+    secretary.clean_cache()
+}
@@ -1,10 +1,17 @@
+use crate::error::error::SecretaryError;
 use crate::models::dropbox_file_metadata::DropboxFileMetadata;
+use crate::models::recording_status::RecordingStatus;
+use crate::models::usage::Usage;
+use crate::obsidian::frontmatter::{self, NoteDocument};
+use crate::obsidian::vault_index::VaultIndex;
+use crate::utils::file_utils::{markdown_files, sanitize_file_name};
+use chrono::{DateTime, Local};
+use std::collections::HashSet;
 use std::fmt;
 use std::fs;
-use tokio::io::AsyncWriteExt;
-use tempfile::NamedTempFile;
-use std::path::PathBuf;
-use std::error::Error;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_PROMPT_PROFILE: &str = "default";
 
 #[derive(Clone)]
 pub struct AudioNote {
@@ -14,6 +21,24 @@ pub struct AudioNote {
     pub note_name: String,
     pub local_audio_file_path: PathBuf,
     pub note_path: PathBuf,
+    pub status: RecordingStatus,
+    pub duration_seconds: Option<f64>,
+    pub cost: f64,
+    /// What the transcription and analysis consumed; `cost` is its price.
+    pub usage: Usage,
+    pub prompt_profile: String,
+    /// The profile was picked by the user, so no rule may change it.
+    pub profile_requested: bool,
+    /// Markdown embedding the recording at the top of the note, if attached.
+    pub audio_embed: Option<String>,
+    /// The chat model that wrote the note.
+    pub model: String,
+    /// The spoken language Whisper detected.
+    pub language: Option<String>,
+    /// Vault folder for a new note, relative to the vault; empty for its root.
+    pub folder: String,
+    /// Tags added to the note besides those the model chose.
+    pub tags: Vec<String>,
 }
 
 impl AudioNote{
@@ -31,93 +56,163 @@ impl AudioNote{
             note_name,
             local_audio_file_path: audio_file_path,
             note_path,
+            status: RecordingStatus::Discovered,
+            duration_seconds: None,
+            cost: 0.0,
+            usage: Usage::default(),
+            prompt_profile: DEFAULT_PROMPT_PROFILE.to_string(),
+            profile_requested: false,
+            audio_embed: None,
+            model: "".to_string(),
+            language: None,
+            folder: "".to_string(),
+            tags: Vec::new(),
         }
     }
     
-    pub fn check_if_new_file(&mut self, path: &PathBuf) -> bool {
-        // Attempt to read the directory entries
-        let dir_entries = match fs::read_dir(path) {
-            Ok(entries) => entries,
-            Err(e) => {
-                eprintln!("Failed to read directory: {}", e);
-                return false;  // Decide on default behavior in case of error
-            }
-        };
-
-        // Iterate over each entry in the directory
-        for entry in dir_entries {
-            let entry = match entry {
-                Ok(e) => e,
-                Err(e) => {
-                    eprintln!("Error reading directory entry: {}", e);
-                    continue;  // Continue with next entry on error
-                }
-            };
-            let entry_path = entry.path();
-
-            // Check if the file has a '.md' extension
-            if entry_path.extension().and_then(std::ffi::OsStr::to_str) == Some("md") {
-                // Read the content of the file
-                let content = match fs::read_to_string(&entry_path) {
-                    Ok(c) => c,
-                    Err(e) => {
-                        eprintln!("Error reading file '{}': {}", entry_path.display(), e);
-                        continue;  // Continue with next file on error
-                    }
-                };
-
-                // Check for the specific marker in the content
-                let marker = format!("audio_file_name: {}", self.audio_file_metadata.name);
-                if content.contains(&marker) {
-                    return false;
-                }
-            }
+    /// Whether the recording has no note yet. The note at `note_path` is
+    /// checked first; `vault` is only read when that isn't it, and only once
+    /// for all recordings that need it.
+    pub fn check_if_new_file(&self, vault: &mut Option<VaultIndex>, vault_path: &Path) -> bool {
+        if self.has_note_at_path() {
+            return false;
         }
-
-        // If no matching file is found or all files were processed with errors, return true
-        true
+        vault
+            .get_or_insert_with(|| VaultIndex::build(vault_path))
+            .find(self)
+            .is_none()
     }
 
+    /// Whether `note_path` is this recording's note: it may have been renamed
+    /// or deleted since.
+    fn has_note_at_path(&self) -> bool {
+        fs::read_to_string(&self.note_path)
+            .is_ok_and(|content| frontmatter::belongs_to(&content, self))
+    }
 
+    /// The note already made from this recording: the one at `note_path` if it
+    /// still belongs to it (it may have been renamed), or else one anywhere in
+    /// `path`, outside hidden folders such as `.obsidian`.
+    pub fn find_existing_note(&self, path: &Path) -> Option<PathBuf> {
+        if self.has_note_at_path() {
+            return Some(self.note_path.clone());
+        }
 
+        markdown_files(path)
+            .into_iter()
+            .find(|entry_path| match fs::read_to_string(entry_path) {
+                Ok(content) => frontmatter::belongs_to(&content, self),
+                Err(e) => {
+                    eprintln!("Error reading file '{}': {}", entry_path.display(), e);
+                    false
+                }
+            })
+    }
 
-
-
-    pub async fn save_audio_file(&mut self, audio_file: Vec<u8>) -> Result<&AudioNote, Box<dyn Error>> {
-        // Create a temporary file in the default temporary directory.
-        let temp_file = NamedTempFile::new()?;
-
-        // Write the audio file content to the temporary file asynchronously using tokio::fs::File.
-        let mut file = tokio::fs::File::from_std(temp_file.reopen()?);
-        file.write_all(&audio_file).await?;
-        file.flush().await?;
-
-        // Persist the temporary file and update the path.
-        let persisted_path = temp_file.into_temp_path();
-        let persisted_path_buf = persisted_path.to_path_buf();
-        persisted_path.persist(&persisted_path_buf)?;
-        self.local_audio_file_path = persisted_path_buf;
-
-        println!("Audio file saved to: {}", self.local_audio_file_path.display());
-        Ok(self)
+    /// When the recording was made, in local time; now if Dropbox didn't say.
+    pub fn recorded_at(&self) -> DateTime<Local> {
+        DateTime::parse_from_rfc3339(&self.audio_file_metadata.client_modified)
+            .map(|time| time.with_timezone(&Local))
+            .unwrap_or_else(|_| Local::now())
     }
 
-    pub async fn make_note_name_from_title (&mut self) -> Result<&mut Self, Box<dyn Error>> {
-        let title_regex = regex::Regex::new(r"title:\s*(.+)").unwrap();
-        self.note_name = title_regex
-            .captures(&self.note)
-            .and_then(|captures| captures.get(1))
-            .map(|title| title.as_str().to_string())
+    pub async fn make_note_name_from_title(&mut self) -> Result<&mut Self, SecretaryError> {
+        self.note_name = NoteDocument::parse(&self.note)
+            .0
+            .title()
             .unwrap_or_else(|| "Untitled".to_string());
         Ok(self)
     }
 
-    pub async fn make_note_path (&mut self, path: &String) -> Result<&mut Self, Box<dyn Error>> {
-        self.note_path = PathBuf::from(path);
-        self.note_path.push(format!("{}.md", &self.note_name));
-        Ok(self)
+    /// Fills in a note naming pattern. `{title}` is the note name, `{date}` and
+    /// `{time}` are when the recording was made, in local time like the daily
+    /// notes, `{file}` is the recording's file name without extension and
+    /// `{profile}` the prompt profile.
+    pub fn expand_name_pattern(&self, pattern: &str) -> String {
+        let recorded_at = self.recorded_at();
+        let date = recorded_at.format("%Y-%m-%d").to_string();
+        let time = recorded_at.format("%H-%M").to_string();
+        let file = Path::new(&self.audio_file_metadata.name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        pattern
+            .replace("{title}", &self.note_name)
+            .replace("{date}", &date)
+            .replace("{time}", &time)
+            .replace("{file}", &file)
+            .replace("{profile}", &self.prompt_profile)
     }
 
+    /// Picks the note's file in `folder`, named after `pattern` and made safe
+    /// by `sanitize_file_name`. The file is created right away to reserve the name.
+    /// A note belonging to another recording is never overwritten; a numeric
+    /// suffix is added instead (`Title 2.md`).
+    pub async fn make_note_path(
+        &mut self,
+        folder: &Path,
+        pattern: &str,
+    ) -> Result<&mut Self, SecretaryError> {
+        let name = sanitize_file_name(&self.expand_name_pattern(pattern));
+
+        let mut attempt = 1;
+        loop {
+            let path = note_file_path(folder, &name, attempt);
+            match tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => {
+                    self.note_path = path;
+                    return Ok(self);
+                }
+                Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                    // This recording's own note, e.g. when reprocessing: replace it
+                    let content = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+                    if frontmatter::belongs_to(&content, self) {
+                        self.note_path = path;
+                        return Ok(self);
+                    }
+                }
+                Err(error) => return Err(error.into()),
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Where `make_note_path` would put the note, without creating anything,
+    /// treating `planned` paths as taken too. Also tells whether the preferred
+    /// name was taken.
+    pub fn plan_note_path(
+        &self,
+        folder: &Path,
+        pattern: &str,
+        planned: &HashSet<PathBuf>,
+    ) -> (PathBuf, bool) {
+        let name = sanitize_file_name(&self.expand_name_pattern(pattern));
+        let mut attempt = 1;
+        loop {
+            let path = note_file_path(folder, &name, attempt);
+            let taken = planned.contains(&path)
+                || fs::read_to_string(&path)
+                    .is_ok_and(|content| !frontmatter::belongs_to(&content, self));
+            if !taken {
+                return (path, attempt > 1);
+            }
+            attempt += 1;
+        }
+    }
+}
+
+/// The note file for `name`, with a numeric suffix after the first attempt.
+fn note_file_path(folder: &Path, name: &str, attempt: u32) -> PathBuf {
+    let file_name = match attempt {
+        1 => format!("{}.md", name),
+        _ => format!("{} {}.md", name, attempt),
+    };
+    folder.join(file_name)
 }
 
 impl fmt::Display for AudioNote {
@@ -1,13 +1,28 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::path::Path;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DropboxFileMetadata {
+    pub id: String,
     pub name: String,
     pub path_lower: String,
     pub client_modified: String,
     pub server_modified: String,
     pub size: u64,
+    /// Dropbox content hash, used to verify downloads. Missing in old ledgers.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+}
+
+impl DropboxFileMetadata {
+    /// The Dropbox folder the file is in, lowercase and without a trailing `/`.
+    pub fn folder(&self) -> String {
+        Path::new(&self.path_lower)
+            .parent()
+            .map(|folder| folder.to_string_lossy().trim_end_matches('/').to_string())
+            .unwrap_or_default()
+    }
 }
 
 impl fmt::Display for DropboxFileMetadata {
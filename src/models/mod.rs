@@ -1,2 +1,4 @@
 pub mod audio_note;
 pub mod dropbox_file_metadata;
+pub mod recording_status;
+pub mod usage;
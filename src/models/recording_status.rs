@@ -0,0 +1,25 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RecordingStatus {
+    Discovered,
+    Downloaded,
+    Transcribed,
+    Analyzed,
+    Saved,
+    Failed(String),
+}
+
+impl fmt::Display for RecordingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingStatus::Discovered => write!(f, "Discovered"),
+            RecordingStatus::Downloaded => write!(f, "Downloaded"),
+            RecordingStatus::Transcribed => write!(f, "Transcribed"),
+            RecordingStatus::Analyzed => write!(f, "Analyzed"),
+            RecordingStatus::Saved => write!(f, "Saved"),
+            RecordingStatus::Failed(error) => write!(f, "Failed: {}", error),
+        }
+    }
+}
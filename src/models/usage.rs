@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use std::ops::AddAssign;
+
+/// What processing a recording consumed from the OpenAI APIs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Usage {
+    pub audio_minutes: f64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.audio_minutes += other.audio_minutes;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
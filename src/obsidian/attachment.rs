@@ -0,0 +1,97 @@
+use crate::config::config::{AudioAttachment, ObsidianConfig};
+use crate::dropbox::dropbox::DropboxClient;
+use crate::error::error::SecretaryError;
+use crate::models::audio_note::AudioNote;
+use crate::utils::file_utils::sanitize_file_name;
+use std::path::Path;
+use tokio::process::Command;
+
+/// Makes the recording of `audio_note` playable from its note, by copying it
+/// into the vault or by creating a Dropbox shared link. Returns the markdown
+/// that embeds it, or `None` when attachments are turned off.
+pub async fn attach_audio(
+    config: &ObsidianConfig,
+    dropbox_client: &DropboxClient,
+    audio_note: &AudioNote,
+) -> Result<Option<String>, SecretaryError> {
+    match config.audio_attachment {
+        AudioAttachment::None => Ok(None),
+        AudioAttachment::Copy => {
+            let file_name = copy_to_vault(config, audio_note).await?;
+            Ok(Some(format!("![[{}]]", file_name)))
+        }
+        AudioAttachment::SharedLink => {
+            let link = dropbox_client
+                .shared_link(&audio_note.audio_file_metadata.path_lower)
+                .await?;
+            // `raw=1` serves the file itself instead of the Dropbox preview page
+            let link = link.replace("dl=0", "raw=1");
+            Ok(Some(format!(
+                "<audio controls src=\"{}\"></audio>",
+                link.replace('"', "%22")
+            )))
+        }
+    }
+}
+
+/// Copies (or transcodes) the downloaded recording into the attachments folder
+/// and returns its file name there. The name includes the Dropbox id, since
+/// many recorder apps give every recording the same name; reprocessing a
+/// recording replaces its own copy.
+async fn copy_to_vault(
+    config: &ObsidianConfig,
+    audio_note: &AudioNote,
+) -> Result<String, SecretaryError> {
+    let attachments_dir = Path::new(&config.vault_path).join(&config.attachments_dir);
+    tokio::fs::create_dir_all(&attachments_dir).await?;
+
+    let original_name = &audio_note.audio_file_metadata.name;
+    let original = Path::new(original_name);
+    let stem = format!(
+        "{} {}",
+        sanitize_file_name(&original.file_stem().unwrap_or_default().to_string_lossy()),
+        sanitize_file_name(audio_note.audio_file_metadata.id.trim_start_matches("id:"))
+    );
+    if config.transcode_command.is_empty() {
+        let file_name = match original.extension() {
+            Some(extension) => format!("{}.{}", stem, extension.to_string_lossy()),
+            None => stem,
+        };
+        tokio::fs::copy(
+            &audio_note.local_audio_file_path,
+            attachments_dir.join(&file_name),
+        )
+        .await?;
+        return Ok(file_name);
+    }
+
+    let file_name = format!("{}.{}", stem, config.transcode_extension);
+    let command = config
+        .transcode_command
+        .replace("{input}", &shell_quote(&audio_note.local_audio_file_path))
+        .replace("{output}", &shell_quote(&attachments_dir.join(&file_name)));
+
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", &command]).output().await?
+    } else {
+        Command::new("sh").args(["-c", &command]).output().await?
+    };
+    if !output.status.success() {
+        return Err(SecretaryError::Config(format!(
+            "transcode command failed for '{}' ({}): {}",
+            original_name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(file_name)
+}
+
+fn shell_quote(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        format!("\"{}\"", path)
+    } else {
+        format!("'{}'", path.replace('\'', r"'\''"))
+    }
+}
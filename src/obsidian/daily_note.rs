@@ -0,0 +1,116 @@
+use crate::config::config::DailyNotesConfig;
+use crate::error::error::SecretaryError;
+use crate::models::audio_note::AudioNote;
+use crate::obsidian::frontmatter::NoteDocument;
+use crate::obsidian::regenerate::upsert_section;
+use crate::obsidian::tasks::action_items;
+use chrono::{DateTime, Local};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Adds saved notes to the daily note of the day they were recorded.
+pub struct DailyNotes {
+    config: DailyNotesConfig,
+    vault_path: PathBuf,
+    /// Recordings are saved concurrently, often into the same daily note.
+    lock: Mutex<()>,
+}
+
+impl DailyNotes {
+    pub fn new(config: &DailyNotesConfig, vault_path: &str) -> Self {
+        DailyNotes {
+            config: config.clone(),
+            vault_path: PathBuf::from(vault_path),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// The daily note for the local day `audio_note` was recorded.
+    pub fn path_for(&self, audio_note: &AudioNote) -> PathBuf {
+        self.vault_path.join(
+            audio_note
+                .recorded_at()
+                .format(&self.config.path_format)
+                .to_string(),
+        )
+    }
+
+    /// Adds or updates the entry for `audio_note` under the configured heading:
+    /// a link to its note with a one-line summary, followed by its action
+    /// items. The entry is marked with the recording's id, so processing it
+    /// again replaces the entry instead of adding another one. A missing daily
+    /// note is created from the template.
+    pub fn add_entry(&self, audio_note: &AudioNote) -> Result<PathBuf, SecretaryError> {
+        let (document, _) = NoteDocument::parse(&audio_note.note);
+        let mut entry = format!("- {}", note_link(&self.vault_path, &audio_note.note_path));
+        if let Some(summary) = document.summary() {
+            entry.push_str(&format!(" — {}", summary));
+        }
+        for item in action_items(&document, audio_note.recorded_at().date_naive()) {
+            entry.push_str(&format!("\n    - [ ] {}", item.render(None)));
+        }
+
+        let path = self.path_for(audio_note);
+        let _guard = self.lock.lock().unwrap();
+        let existing = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                self.new_daily_note(&path, audio_note.recorded_at())?
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let updated = upsert_section(
+            &existing,
+            &self.config.heading,
+            &audio_note.audio_file_metadata.id,
+            &entry,
+        );
+        if updated != existing {
+            if let Some(folder) = path.parent() {
+                fs::create_dir_all(folder)?;
+            }
+            fs::write(&path, updated)?;
+        }
+        Ok(path)
+    }
+
+    /// The text a new daily note at `path` starts with.
+    fn new_daily_note(&self, path: &Path, day: DateTime<Local>) -> Result<String, SecretaryError> {
+        if self.config.template.is_empty() {
+            return Ok("".to_string());
+        }
+        let template = fs::read_to_string(self.vault_path.join(&self.config.template))?;
+        let title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(template
+            .replace("{{date}}", &day.format("%Y-%m-%d").to_string())
+            .replace("{{title}}", &title))
+    }
+}
+
+/// A wiki link to the note at `note_path`. Notes outside the vault root are
+/// linked by their path in the vault, so the link is unambiguous.
+pub fn note_link(vault_path: &Path, note_path: &Path) -> String {
+    let name = note_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let folder = note_path
+        .parent()
+        .and_then(|folder| folder.strip_prefix(vault_path).ok())
+        .map(|folder| folder.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_default();
+    if folder.is_empty() {
+        format!("[[{}]]", name)
+    } else {
+        format!("[[{}/{}|{}]]", folder, name, name)
+    }
+}
@@ -0,0 +1,467 @@
+use crate::error::error::SecretaryError;
+use crate::models::audio_note::AudioNote;
+use serde_yaml::{Mapping, Value};
+
+/// Frontmatter keys Secretary sets itself. They replace whatever the model
+/// wrote under the same names.
+pub const SECRETARY_KEYS: [&str; 11] = [
+    "audio_file_name",
+    "dropbox_id",
+    "recorded_at",
+    "duration",
+    "model",
+    "language",
+    "processed_at",
+    "audio_minutes",
+    "prompt_tokens",
+    "completion_tokens",
+    "cost",
+];
+
+/// A markdown note split into its YAML frontmatter and its body.
+#[derive(Clone, Debug, Default)]
+pub struct NoteDocument {
+    pub frontmatter: Mapping,
+    pub body: String,
+}
+
+impl NoteDocument {
+    /// Splits `text` into frontmatter and body. Frontmatter that isn't valid YAML
+    /// is repaired if possible; otherwise it is kept in the body as a code block
+    /// so nothing is lost, and the problem is returned for reporting.
+    pub fn parse(text: &str) -> (NoteDocument, Option<String>) {
+        let text = strip_code_fence(text);
+        let Some((yaml, body)) = split_frontmatter(text) else {
+            return (
+                NoteDocument {
+                    frontmatter: Mapping::new(),
+                    body: text.trim_start().to_string(),
+                },
+                None,
+            );
+        };
+
+        match parse_mapping(yaml) {
+            Ok(frontmatter) => (
+                NoteDocument {
+                    frontmatter,
+                    body: body.to_string(),
+                },
+                None,
+            ),
+            Err(error) => match parse_mapping(&repair(yaml)) {
+                Ok(frontmatter) => (
+                    NoteDocument {
+                        frontmatter,
+                        body: body.to_string(),
+                    },
+                    Some(format!("repaired invalid frontmatter ({})", error)),
+                ),
+                Err(_) => (
+                    NoteDocument {
+                        frontmatter: Mapping::new(),
+                        body: format!("```yaml\n{}\n```\n\n{}", yaml.trim_end(), body),
+                    },
+                    Some(format!(
+                        "frontmatter is not valid YAML and was moved into the note body ({})",
+                        error
+                    )),
+                ),
+            },
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.frontmatter.get(key).and_then(Value::as_str)
+    }
+
+    /// The values of a list key. A string counts as a comma-separated list, and
+    /// links (`[[Name]]`) count as their target.
+    pub fn get_list(&self, key: &str) -> Vec<String> {
+        let values: Vec<String> = match self.frontmatter.get(key) {
+            Some(Value::Sequence(list)) => list
+                .iter()
+                .filter_map(|value| value.as_str().map(str::to_string))
+                .collect(),
+            Some(Value::String(text)) => text.split(',').map(str::to_string).collect(),
+            _ => Vec::new(),
+        };
+        values
+            .iter()
+            .map(|value| value.trim().trim_start_matches("[[").trim_end_matches("]]"))
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The people the note names under `person`, `people` or `attendees`.
+    pub fn people(&self) -> Vec<String> {
+        ["person", "people", "attendees"]
+            .iter()
+            .flat_map(|key| self.get_list(key))
+            .collect()
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<Value>) {
+        self.frontmatter.insert(Value::from(key), value.into());
+    }
+
+    /// The note's title: the `title` key, or else its first level 1 heading.
+    pub fn title(&self) -> Option<String> {
+        let title = match self.frontmatter.get("title") {
+            Some(Value::String(title)) => Some(title.clone()),
+            Some(Value::Number(number)) => Some(number.to_string()),
+            _ => self.body.lines().find_map(|line| {
+                line.strip_prefix("# ")
+                    .map(|heading| {
+                        heading
+                            .trim()
+                            .trim_start_matches("[[")
+                            .trim_end_matches("]]")
+                    })
+                    .map(str::to_string)
+            }),
+        };
+        title
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty())
+    }
+
+    /// The text under the body's first heading named `heading` (ignoring case,
+    /// level and a trailing colon), up to the next heading of the same or a
+    /// higher level.
+    pub fn section(&self, heading: &str) -> Option<String> {
+        let mut lines = self.body.lines();
+        let level = lines.find_map(|line| {
+            parse_heading(line)
+                .filter(|(_, name)| name.eq_ignore_ascii_case(heading))
+                .map(|(level, _)| level)
+        })?;
+        Some(
+            lines
+                .take_while(|line| parse_heading(line).is_none_or(|(other, _)| other > level))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+
+    /// A one-line summary: the `summary` key, or else the first paragraph
+    /// under the `Summary` heading.
+    pub fn summary(&self) -> Option<String> {
+        let summary = match self.get_str("summary") {
+            Some(summary) => summary.to_string(),
+            None => self
+                .section("Summary")?
+                .trim_start()
+                .split("\n\n")
+                .next()?
+                .lines()
+                .filter(|line| !line.trim_start().starts_with("<!--"))
+                .collect::<Vec<_>>()
+                .join(" "),
+        };
+        let summary = summary.split_whitespace().collect::<Vec<_>>().join(" ");
+        (!summary.is_empty()).then_some(summary)
+    }
+
+    /// The items of the list under `heading`, without their bullets or checkboxes.
+    pub fn list_items(&self, heading: &str) -> Vec<String> {
+        let Some(section) = self.section(heading) else {
+            return Vec::new();
+        };
+        section
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                let item = line
+                    .strip_prefix("- ")
+                    .or_else(|| line.strip_prefix("* "))
+                    .or_else(|| {
+                        let (number, item) = line.split_once(". ")?;
+                        (!number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+                            .then_some(item)
+                    })?;
+                let item = ["[ ] ", "[x] ", "[X] "]
+                    .iter()
+                    .find_map(|checkbox| item.strip_prefix(checkbox))
+                    .unwrap_or(item)
+                    .trim();
+                (!item.is_empty()).then(|| item.to_string())
+            })
+            .collect()
+    }
+
+    /// Sets the keys Secretary owns from `audio_note`, after the model's keys.
+    /// Keys without a value (e.g. no detected language) are left out, and so
+    /// is the usage unless `include_usage` is set.
+    pub fn merge_secretary_keys(
+        &mut self,
+        audio_note: &AudioNote,
+        processed_at: &str,
+        include_usage: bool,
+    ) {
+        for key in SECRETARY_KEYS {
+            self.frontmatter.shift_remove(key);
+        }
+        let metadata = &audio_note.audio_file_metadata;
+        self.set("audio_file_name", metadata.name.as_str());
+        self.set("dropbox_id", metadata.id.as_str());
+        self.set("recorded_at", metadata.client_modified.as_str());
+        if let Some(duration) = audio_note.duration_seconds {
+            self.set("duration", (duration * 10.0).round() / 10.0);
+        }
+        if !audio_note.model.is_empty() {
+            self.set("model", audio_note.model.as_str());
+        }
+        if let Some(language) = &audio_note.language {
+            self.set("language", language.as_str());
+        }
+        self.set("processed_at", processed_at);
+        if include_usage {
+            let usage = &audio_note.usage;
+            self.set(
+                "audio_minutes",
+                (usage.audio_minutes * 100.0).round() / 100.0,
+            );
+            self.set("prompt_tokens", usage.prompt_tokens);
+            self.set("completion_tokens", usage.completion_tokens);
+            self.set("cost", (audio_note.cost * 10_000.0).round() / 10_000.0);
+        }
+    }
+
+    /// Adds `tags` to the `tags` list that are not in it yet. A single tag
+    /// written as a string becomes a list.
+    pub fn add_tags(&mut self, tags: &[String]) {
+        if tags.is_empty() {
+            return;
+        }
+        let mut list = match self.frontmatter.get("tags") {
+            Some(Value::Sequence(list)) => list.clone(),
+            Some(Value::String(tag)) => tag
+                .split([',', ' '])
+                .filter(|tag| !tag.is_empty())
+                .map(Value::from)
+                .collect(),
+            _ => Vec::new(),
+        };
+        for tag in tags {
+            let tag = tag.trim_start_matches('#');
+            if !list.iter().any(|existing| existing.as_str() == Some(tag)) {
+                list.push(Value::from(tag));
+            }
+        }
+        self.set("tags", list);
+    }
+
+    pub fn render(&self) -> Result<String, SecretaryError> {
+        if self.frontmatter.is_empty() {
+            return Ok(self.body.clone());
+        }
+        let yaml = serde_yaml::to_string(&self.frontmatter)
+            .map_err(|error| SecretaryError::Decode(error.to_string()))?;
+        Ok(format!("---\n{}---\n{}", yaml, self.body))
+    }
+}
+
+/// Whether the note `content` was made from the recording in `audio_note`: its
+/// frontmatter names the same Dropbox id or, for notes written before the id
+/// was recorded, the same file name.
+pub fn belongs_to(content: &str, audio_note: &AudioNote) -> bool {
+    let metadata = &audio_note.audio_file_metadata;
+    let (document, _) = NoteDocument::parse(content);
+    match document.get_str("dropbox_id") {
+        Some(id) => id == metadata.id,
+        None => {
+            document.get_str("audio_file_name") == Some(metadata.name.as_str())
+                || content
+                    .lines()
+                    .any(|line| line.trim() == format!("audio_file_name: {}", metadata.name))
+        }
+    }
+}
+
+/// The level and name of a markdown heading line (`## Notes:` is 2 and
+/// `Notes`), if it is one.
+pub fn parse_heading(line: &str) -> Option<(usize, &str)> {
+    let line = line.trim();
+    let level = line.chars().take_while(|&c| c == '#').count();
+    (level > 0 && line[level..].starts_with(' '))
+        .then(|| (level, line[level..].trim().trim_end_matches(':').trim_end()))
+}
+
+/// Models sometimes wrap the whole answer in a ```markdown fence.
+fn strip_code_fence(text: &str) -> &str {
+    let trimmed = text.trim();
+    if let Some(inner) = trimmed.strip_prefix("```") {
+        if let Some((_, inner)) = inner.split_once('\n') {
+            if let Some(inner) = inner.trim_end().strip_suffix("```") {
+                return inner;
+            }
+        }
+    }
+    text
+}
+
+/// Returns the YAML between the opening and closing `---` lines, and the rest.
+fn split_frontmatter(text: &str) -> Option<(&str, &str)> {
+    let rest = text.trim_start().strip_prefix("---")?;
+    let rest = rest.strip_prefix('\r').unwrap_or(rest).strip_prefix('\n')?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let body = &rest[offset + line.len()..];
+            return Some((&rest[..offset], body.trim_start_matches(['\r', '\n'])));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn parse_mapping(yaml: &str) -> Result<Mapping, serde_yaml::Error> {
+    match serde_yaml::from_str::<Value>(yaml)? {
+        Value::Mapping(mapping) => Ok(mapping),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err(serde::de::Error::custom("frontmatter is not a mapping")),
+    }
+}
+
+/// Fixes the mistakes models make most: tabs for indentation, unquoted values
+/// (or list items) containing `: `, ` #` or leading YAML indicators, and keys
+/// given more than once, of which the last is kept.
+fn repair(yaml: &str) -> String {
+    let lines: Vec<String> = yaml
+        .lines()
+        .map(|line| {
+            let line = line.replace('\t', "  ");
+            let indent = line.len() - line.trim_start().len();
+            let content = &line[indent..];
+            if let Some(item) = content.strip_prefix("- ") {
+                return format!("{}- {}", &line[..indent], quote_if_needed(item));
+            }
+            match content.split_once(':') {
+                Some((key, value)) if !key.contains(' ') && !value.trim().is_empty() => {
+                    format!("{}{}: {}", &line[..indent], key, quote_if_needed(value))
+                }
+                _ => line,
+            }
+        })
+        .collect();
+
+    // Each top-level key with the indented lines and list items under it
+    let mut entries: Vec<(Option<&str>, Vec<&str>)> = Vec::new();
+    for line in &lines {
+        let nested = line.starts_with([' ', '-']) || line.trim().is_empty();
+        match entries.last_mut() {
+            Some((_, entry)) if nested => entry.push(line),
+            _ => entries.push((line.split_once(':').map(|(key, _)| key), vec![line])),
+        }
+    }
+    let mut kept: Vec<&str> = Vec::new();
+    for (index, (key, entry)) in entries.iter().enumerate() {
+        let repeated = key.is_some() && entries[index + 1..].iter().any(|(other, _)| other == key);
+        if !repeated {
+            kept.extend(entry);
+        }
+    }
+    kept.join("\n")
+}
+
+fn quote_if_needed(value: &str) -> String {
+    let value = value.trim();
+    let quoted = value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')));
+    let flow = (value.starts_with('[') && value.ends_with(']'))
+        || (value.starts_with('{') && value.ends_with('}'));
+    let needs_quotes = value.contains(": ")
+        || value.contains(" #")
+        || value.starts_with(['*', '&', '!', '%', '@', '`', '|', '>', '[', '{', '"', '\'']);
+    if quoted || flow || !needs_quotes {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_without_frontmatter_is_all_body() {
+        let (document, problem) = NoteDocument::parse("# Title\n\nSome text\n");
+        assert!(document.frontmatter.is_empty());
+        assert_eq!(document.body, "# Title\n\nSome text\n");
+        assert_eq!(problem, None);
+        assert_eq!(document.render().unwrap(), "# Title\n\nSome text\n");
+    }
+
+    #[test]
+    fn code_fence_around_the_note_is_removed() {
+        let (document, _) = NoteDocument::parse("```markdown\n---\ntitle: Sync\n---\nBody\n```");
+        assert_eq!(document.title().as_deref(), Some("Sync"));
+        assert_eq!(document.body.trim(), "Body");
+    }
+
+    #[test]
+    fn invalid_yaml_is_repaired() {
+        let text = "---\ntitle: Budget: Q3 review\ntags:\n\t- finance\n\t- @planning\n---\nBody\n";
+        let (document, problem) = NoteDocument::parse(text);
+        assert!(problem.unwrap().starts_with("repaired invalid frontmatter"));
+        assert_eq!(document.get_str("title"), Some("Budget: Q3 review"));
+        assert_eq!(document.get_list("tags"), ["finance", "@planning"]);
+        assert_eq!(document.body, "Body\n");
+    }
+
+    #[test]
+    fn duplicate_keys_keep_the_last_value() {
+        let text = "---\ntitle: Draft\ntags:\n  - old\nsummary: Short\ntitle: Final\ntags:\n  - new\n---\nBody\n";
+        let (document, problem) = NoteDocument::parse(text);
+        assert!(problem.is_some());
+        assert_eq!(document.get_str("title"), Some("Final"));
+        assert_eq!(document.get_list("tags"), ["new"]);
+        assert_eq!(document.get_str("summary"), Some("Short"));
+    }
+
+    #[test]
+    fn unrepairable_yaml_moves_into_the_body() {
+        let text = "---\ntitle: Sync\n- stray item\n---\nBody\n";
+        let (document, problem) = NoteDocument::parse(text);
+        assert!(document.frontmatter.is_empty());
+        assert!(document
+            .body
+            .starts_with("```yaml\ntitle: Sync\n- stray item\n```"));
+        assert!(document.body.ends_with("Body\n"));
+        assert!(problem.unwrap().contains("moved into the note body"));
+    }
+
+    #[test]
+    fn round_trip_keeps_unknown_keys() {
+        let text =
+            "---\ntitle: Sync\nmy_rating: 5\nreviewed: true\nlinks:\n- '[[Project]]'\n---\nBody\n";
+        let (mut document, problem) = NoteDocument::parse(text);
+        assert_eq!(problem, None);
+        document.set("language", "en");
+        let rendered = document.render().unwrap();
+
+        let (reparsed, _) = NoteDocument::parse(&rendered);
+        assert_eq!(reparsed.get_str("title"), Some("Sync"));
+        assert_eq!(reparsed.frontmatter.get("my_rating"), Some(&Value::from(5)));
+        assert_eq!(
+            reparsed.frontmatter.get("reviewed"),
+            Some(&Value::from(true))
+        );
+        assert_eq!(reparsed.get_list("links"), ["Project"]);
+        assert_eq!(reparsed.get_str("language"), Some("en"));
+        assert_eq!(reparsed.body, "Body\n");
+        let keys: Vec<&str> = reparsed
+            .frontmatter
+            .keys()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(
+            keys,
+            ["title", "my_rating", "reviewed", "links", "language"]
+        );
+    }
+}
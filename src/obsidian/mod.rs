@@ -0,0 +1,7 @@
+pub mod attachment;
+pub mod daily_note;
+pub mod frontmatter;
+pub mod regenerate;
+pub mod routing;
+pub mod tasks;
+pub mod vault_index;
@@ -0,0 +1,270 @@
+use crate::error::error::SecretaryError;
+use crate::obsidian::frontmatter::{parse_heading, NoteDocument};
+
+/// Wraps generated `content` in markers naming the section, so a later
+/// regeneration can replace it without touching the rest of the note.
+pub fn managed_section(name: &str, content: &str) -> String {
+    format!(
+        "{}\n{}\n{}\n",
+        begin_marker(name),
+        content.trim_end_matches('\n'),
+        end_marker(name)
+    )
+}
+
+/// Merges a freshly generated note into the `existing` one. Frontmatter keys
+/// and managed sections present in `regenerated` replace their old values;
+/// everything else (keys and text the user added) is left as it is. A managed
+/// section that is new is placed after the section it follows in `regenerated`.
+/// Notes from before sections were marked are all the user's text, so the
+/// managed sections go above it.
+pub fn merge_note(existing: &str, regenerated: &str) -> Result<String, SecretaryError> {
+    let (mut merged, _) = NoteDocument::parse(existing);
+    let (generated, _) = NoteDocument::parse(regenerated);
+
+    for (key, value) in generated.frontmatter {
+        merged.frontmatter.insert(key, value);
+    }
+
+    let mut parts = split_sections(&merged.body);
+
+    let mut previous: Option<usize> = None;
+    for section in split_sections(&generated.body) {
+        let Some(name) = &section.section else {
+            continue;
+        };
+        let index = match parts
+            .iter()
+            .position(|part| part.section.as_ref() == Some(name))
+        {
+            Some(index) => {
+                parts[index] = section;
+                index
+            }
+            None => {
+                let index = previous.map_or(0, |previous| previous + 1);
+                parts.insert(index, section);
+                index
+            }
+        };
+        previous = Some(index);
+    }
+
+    merged.body = String::new();
+    for part in parts {
+        if !merged.body.is_empty() && !merged.body.ends_with('\n') {
+            merged.body.push('\n');
+        }
+        merged.body.push_str(&part.text);
+    }
+    merged.render()
+}
+
+/// A run of note text: a managed section with its markers, or anything between.
+struct Part {
+    section: Option<String>,
+    text: String,
+}
+
+fn split_sections(body: &str) -> Vec<Part> {
+    let lines: Vec<&str> = body.split_inclusive('\n').collect();
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let section = section_name(lines[i]).and_then(|name| {
+            let end = end_marker(name);
+            let length = lines[i..].iter().position(|line| line.trim() == end)?;
+            Some((name.to_string(), length + 1))
+        });
+        match section {
+            Some((name, length)) => {
+                if !text.is_empty() {
+                    parts.push(Part {
+                        section: None,
+                        text: std::mem::take(&mut text),
+                    });
+                }
+                parts.push(Part {
+                    section: Some(name),
+                    text: lines[i..i + length].concat(),
+                });
+                i += length;
+            }
+            // An unterminated section is the user's text now
+            None => {
+                text.push_str(lines[i]);
+                i += 1;
+            }
+        }
+    }
+    if !text.is_empty() {
+        parts.push(Part {
+            section: None,
+            text,
+        });
+    }
+    parts
+}
+
+fn section_name(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("<!-- secretary:begin ")?
+        .strip_suffix(" -->")
+}
+
+fn begin_marker(name: &str) -> String {
+    format!("<!-- secretary:begin {} -->", name)
+}
+
+fn end_marker(name: &str) -> String {
+    format!("<!-- secretary:end {} -->", name)
+}
+
+/// The content of the managed section `name` in `text`, without its markers.
+pub fn section_content(text: &str, name: &str) -> Option<String> {
+    let part = split_sections(text)
+        .into_iter()
+        .find(|part| part.section.as_deref() == Some(name))?;
+    let lines: Vec<&str> = part.text.lines().collect();
+    Some(lines[1..lines.len() - 1].join("\n"))
+}
+
+/// Puts `content` into the managed section `name` of `text`. An existing
+/// section is replaced where it is; a new one goes at the end of the part
+/// under `heading`, which is added at the end of `text` if it isn't there.
+pub fn upsert_section(text: &str, heading: &str, name: &str, content: &str) -> String {
+    let section = managed_section(name, content);
+    let mut parts = split_sections(text);
+    if let Some(part) = parts
+        .iter_mut()
+        .find(|part| part.section.as_deref() == Some(name))
+    {
+        part.text = section;
+        return parts.into_iter().map(|part| part.text).collect();
+    }
+
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let Some(start) = lines.iter().position(|line| line.trim() == heading.trim()) else {
+        let text = text.trim_end();
+        let separator = if text.is_empty() { "" } else { "\n\n" };
+        return format!("{}{}{}\n\n{}", text, separator, heading.trim(), section);
+    };
+    let level = parse_heading(heading).map_or(6, |(level, _)| level);
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| parse_heading(line).is_some_and(|(other, _)| other <= level))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    // After the last entry, before the blank lines that end the part
+    let mut insert = end;
+    while insert > start + 1 && lines[insert - 1].trim().is_empty() {
+        insert -= 1;
+    }
+
+    let mut result = lines[..insert].concat();
+    if !result.ends_with('\n') {
+        result.push('\n');
+    }
+    if insert == start + 1 {
+        result.push('\n');
+    }
+    result.push_str(&section);
+    if insert < lines.len() && !lines[insert].trim().is_empty() {
+        result.push('\n');
+    }
+    result.push_str(&lines[insert..].concat());
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(frontmatter: &str, body: &str) -> String {
+        format!("---\n{}---\n{}", frontmatter, body)
+    }
+
+    #[test]
+    fn split_sections_separates_managed_and_user_text() {
+        let body = format!(
+            "Intro\n{}Between\n{}",
+            managed_section("summary", "Old summary"),
+            managed_section("transcript", "Hello")
+        );
+        let parts = split_sections(&body);
+        let sections: Vec<Option<&str>> =
+            parts.iter().map(|part| part.section.as_deref()).collect();
+        assert_eq!(sections, [None, Some("summary"), None, Some("transcript")]);
+        assert_eq!(parts[2].text, "Between\n");
+        assert_eq!(
+            parts
+                .iter()
+                .map(|part| part.text.as_str())
+                .collect::<String>(),
+            body
+        );
+        assert_eq!(
+            section_content(&body, "summary").as_deref(),
+            Some("Old summary")
+        );
+    }
+
+    #[test]
+    fn unterminated_section_is_user_text() {
+        let parts = split_sections("<!-- secretary:begin summary -->\nText\n");
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].section, None);
+    }
+
+    #[test]
+    fn marked_note_gets_its_sections_replaced() {
+        let existing = note(
+            "title: Old\nmine: kept\n",
+            &managed_section("summary", "Old summary"),
+        );
+        let regenerated = note("title: New\n", &managed_section("summary", "New summary"));
+        let merged = merge_note(&existing, &regenerated).unwrap();
+        assert!(merged.contains("New summary"));
+        assert!(!merged.contains("Old summary"));
+        assert!(merged.contains("title: New"));
+        assert!(merged.contains("mine: kept"));
+    }
+
+    #[test]
+    fn user_edits_between_sections_are_kept() {
+        let existing = note(
+            "title: Old\n",
+            &format!(
+                "{}My own notes\n{}",
+                managed_section("summary", "Old summary"),
+                managed_section("transcript", "Old transcript")
+            ),
+        );
+        let regenerated = note(
+            "title: New\n",
+            &format!(
+                "{}{}{}",
+                managed_section("summary", "New summary"),
+                managed_section("actions", "- [ ] Call"),
+                managed_section("transcript", "New transcript")
+            ),
+        );
+        let merged = merge_note(&existing, &regenerated).unwrap();
+        let summary = merged.find("New summary").unwrap();
+        let actions = merged.find("- [ ] Call").unwrap();
+        let own = merged.find("My own notes").unwrap();
+        let transcript = merged.find("New transcript").unwrap();
+        assert!(summary < actions && actions < own && own < transcript);
+        assert!(!merged.contains("Old"));
+    }
+
+    #[test]
+    fn unmarked_note_keeps_its_body() {
+        let existing = note("title: Old\n", "## Summary\nEdited by hand\n");
+        let regenerated = note("title: New\n", &managed_section("summary", "New summary"));
+        let merged = merge_note(&existing, &regenerated).unwrap();
+        let summary = merged.find("New summary").unwrap();
+        let edited = merged.find("Edited by hand").unwrap();
+        assert!(summary < edited);
+    }
+}
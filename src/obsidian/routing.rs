@@ -0,0 +1,67 @@
+use crate::config::config::RouteRule;
+use crate::models::audio_note::AudioNote;
+use crate::obsidian::frontmatter::NoteDocument;
+use crate::utils::file_utils::sanitize_file_name;
+
+/// The vault folder template of the first route the analyzed note matches.
+pub fn route<'a>(
+    routes: &'a [RouteRule],
+    document: &NoteDocument,
+    audio_note: &AudioNote,
+) -> Option<&'a str> {
+    let tags: Vec<String> = document
+        .get_list("tags")
+        .iter()
+        .flat_map(|tags| tags.split_whitespace())
+        .map(|tag| tag.trim_start_matches('#').to_lowercase())
+        .collect();
+    let category = category(document).unwrap_or_default();
+    let people = document.people();
+    let source_folder = audio_note.audio_file_metadata.folder();
+
+    routes
+        .iter()
+        .find(|route| {
+            (route.tag.is_empty()
+                || tags.contains(&route.tag.trim_start_matches('#').to_lowercase()))
+                && (route.category.is_empty() || category.eq_ignore_ascii_case(&route.category))
+                && (route.person.is_empty()
+                    || people
+                        .iter()
+                        .any(|person| person.eq_ignore_ascii_case(&route.person)))
+                && (route.source_folder.is_empty()
+                    || source_folder == route.source_folder.to_lowercase().trim_end_matches('/'))
+        })
+        .map(|route| route.folder.as_str())
+}
+
+/// Fills in a folder template with the recording's local date (`{year}`,
+/// `{month}`, `{day}`, `{date}`), the note's `{category}` and the prompt
+/// `{profile}`.
+/// Values are made safe as folder names, and empty folders are dropped.
+pub fn expand_folder(template: &str, document: &NoteDocument, audio_note: &AudioNote) -> String {
+    let recorded_at = audio_note.recorded_at();
+    let category = category(document)
+        .map(|category| sanitize_file_name(&category))
+        .unwrap_or_else(|| "Uncategorized".to_string());
+    template
+        .replace("{year}", &recorded_at.format("%Y").to_string())
+        .replace("{month}", &recorded_at.format("%m").to_string())
+        .replace("{day}", &recorded_at.format("%d").to_string())
+        .replace("{date}", &recorded_at.format("%Y-%m-%d").to_string())
+        .replace("{category}", &category)
+        .replace("{profile}", &sanitize_file_name(&audio_note.prompt_profile))
+        .split(['/', '\\'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn category(document: &NoteDocument) -> Option<String> {
+    document
+        .get_str("category")
+        .or_else(|| document.get_str("type"))
+        .map(|category| category.trim().to_string())
+        .filter(|category| !category.is_empty())
+}
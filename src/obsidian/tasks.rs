@@ -0,0 +1,314 @@
+use crate::config::config::TasksConfig;
+use crate::error::error::SecretaryError;
+use crate::models::audio_note::AudioNote;
+use crate::obsidian::daily_note::note_link;
+use crate::obsidian::frontmatter::NoteDocument;
+use crate::obsidian::regenerate::{section_content, upsert_section};
+use crate::utils::date_utils::parse_due_date;
+use chrono::NaiveDate;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Words after a person's name that make them the owner, as in "Anna to send…".
+const OWNER_VERBS: [&str; 5] = ["to", "will", "should", "must", "needs to"];
+
+/// An action item from a note, e.g. "Anna to send the deck by Friday".
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActionItem {
+    pub description: String,
+    pub owner: Option<String>,
+    pub due: Option<NaiveDate>,
+}
+
+impl ActionItem {
+    /// Takes the owner and due date out of `text`. The owner is an `@name`, or
+    /// one of `people` the item starts with ("Anna: …", "Anna to …", "Anna
+    /// will …"). Relative due dates count from `today`; a due date already in
+    /// Obsidian Tasks form (`📅 2026-03-10`) is kept.
+    pub fn parse(text: &str, people: &[String], today: NaiveDate) -> ActionItem {
+        let mut text = text.trim().to_string();
+
+        let mut due = None;
+        if let Some((rest, date)) = text.rsplit_once('📅') {
+            if let Ok(date) = NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+                due = Some(date);
+                text = rest.to_string();
+            }
+        }
+        if due.is_none() {
+            if let Some(found) = parse_due_date(&text, today) {
+                text.replace_range(found.range, "");
+                due = Some(found.date);
+            }
+        }
+
+        let mut owner = None;
+        let mention = text
+            .char_indices()
+            .find(|&(index, c)| {
+                c == '@' && text[..index].chars().last().is_none_or(char::is_whitespace)
+            })
+            .map(|(index, _)| index);
+        if let Some(start) = mention {
+            let name = text[start + 1..]
+                .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            if !name.is_empty() {
+                text.replace_range(start..start + 1 + name.len(), "");
+                owner = Some(name);
+            }
+        }
+        if owner.is_none() {
+            if let Some((person, rest)) = people
+                .iter()
+                .find_map(|person| strip_owner(&text, person).map(|rest| (person, rest)))
+            {
+                text = rest;
+                owner = Some(person.clone());
+            }
+        }
+
+        ActionItem {
+            description: tidy(&text),
+            owner,
+            due,
+        }
+    }
+
+    /// The item in Obsidian Tasks form, without the checkbox: the description,
+    /// the owner as a `[owner:: …]` field, `link` and the due date, which
+    /// Tasks expects last.
+    pub fn render(&self, link: Option<&str>) -> String {
+        let mut line = self.description.clone();
+        if let Some(owner) = &self.owner {
+            line.push_str(&format!(" [owner:: {}]", owner));
+        }
+        if let Some(link) = link {
+            line.push(' ');
+            line.push_str(link);
+        }
+        if let Some(due) = self.due {
+            line.push_str(&format!(" 📅 {}", due.format("%Y-%m-%d")));
+        }
+        line
+    }
+}
+
+/// The action items of a note: the list under its `Action Items` heading, or
+/// else its `action_items` key, without repeats.
+pub fn action_items(document: &NoteDocument, today: NaiveDate) -> Vec<ActionItem> {
+    let mut texts = document.list_items("Action Items");
+    if texts.is_empty() {
+        texts = document.get_list("action_items");
+    }
+    let people = document.people();
+    let mut items: Vec<ActionItem> = Vec::new();
+    for text in texts {
+        let item = ActionItem::parse(&text, &people, today);
+        if !item.description.is_empty()
+            && !items
+                .iter()
+                .any(|other| other.description.eq_ignore_ascii_case(&item.description))
+        {
+            items.push(item);
+        }
+    }
+    items
+}
+
+/// Collects the action items of all notes in one task file.
+pub struct TaskFile {
+    config: TasksConfig,
+    vault_path: PathBuf,
+    /// Recordings are saved concurrently, all into the same file.
+    lock: Mutex<()>,
+}
+
+impl TaskFile {
+    pub fn new(config: &TasksConfig, vault_path: &str) -> Self {
+        TaskFile {
+            config: config.clone(),
+            vault_path: PathBuf::from(vault_path),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.vault_path.join(&self.config.file)
+    }
+
+    /// Puts the action items of `audio_note` under the configured heading, each
+    /// linking back to the note, in a block marked with the recording's id.
+    /// Processing the recording again replaces its block, so items aren't
+    /// repeated; items that were checked off stay as they were. Returns how
+    /// many items the note has.
+    pub fn add_items(&self, audio_note: &AudioNote) -> Result<usize, SecretaryError> {
+        let (document, _) = NoteDocument::parse(&audio_note.note);
+        let items = action_items(&document, audio_note.recorded_at().date_naive());
+        let link = note_link(&self.vault_path, &audio_note.note_path);
+
+        let path = self.path();
+        let _guard = self.lock.lock().unwrap();
+        let existing = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => "".to_string(),
+            Err(error) => return Err(error.into()),
+        };
+        let id = &audio_note.audio_file_metadata.id;
+        let previous = section_content(&existing, id);
+        if items.is_empty() && previous.is_none() {
+            return Ok(0);
+        }
+
+        let done: Vec<&str> = previous
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| {
+                let line = line.trim_start();
+                line.starts_with("- [x] ") || line.starts_with("- [X] ")
+            })
+            .collect();
+        let lines: Vec<String> = items
+            .iter()
+            .map(|item| {
+                let line = item.render(Some(&link));
+                done.iter()
+                    .find(|old| {
+                        old.trim_start()[6..].starts_with(&format!("{} ", item.description))
+                    })
+                    .map(|old| old.to_string())
+                    .unwrap_or_else(|| format!("- [ ] {}", line))
+            })
+            .collect();
+
+        let updated = upsert_section(&existing, &self.config.heading, id, &lines.join("\n"));
+        if updated != existing {
+            if let Some(folder) = path.parent() {
+                fs::create_dir_all(folder)?;
+            }
+            fs::write(&path, updated)?;
+        }
+        Ok(items.len())
+    }
+}
+
+/// `text` without `person` and the words that make them its owner, if it
+/// starts with them.
+fn strip_owner(text: &str, person: &str) -> Option<String> {
+    let prefix = text.get(..person.len())?;
+    if person.is_empty() || !prefix.eq_ignore_ascii_case(person) {
+        return None;
+    }
+    let rest = &text[person.len()..];
+    if let Some(rest) = rest.trim_start().strip_prefix([':', '-', '–', '—']) {
+        return Some(rest.to_string());
+    }
+    let rest = rest.strip_prefix(' ')?;
+    OWNER_VERBS.iter().find_map(|verb| {
+        let after = rest.get(..verb.len())?;
+        (after.eq_ignore_ascii_case(verb) && rest[verb.len()..].starts_with(' '))
+            .then(|| rest[verb.len()..].to_string())
+    })
+}
+
+/// Collapses whitespace, drops the punctuation left over at either end once
+/// the owner and due date are cut out, and starts with a capital letter.
+fn tidy(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let text = text.replace(" ,", ",").replace(" .", ".").replace("()", "");
+    let text = text
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | ':' | '-' | '–' | '—'))
+        .trim_end_matches('.')
+        .trim_end();
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => "".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn parse(text: &str) -> ActionItem {
+        let people = vec!["Anna Smith".to_string(), "Bob".to_string()];
+        ActionItem::parse(text, &people, date(2026, 10, 19))
+    }
+
+    #[test]
+    fn mention_becomes_the_owner() {
+        let item = parse("@carol call vendor tomorrow");
+        assert_eq!(item.description, "Call vendor");
+        assert_eq!(item.owner.as_deref(), Some("carol"));
+        assert_eq!(item.due, Some(date(2026, 10, 20)));
+
+        let item = parse("Ask @dan-lee about the budget");
+        assert_eq!(item.description, "Ask about the budget");
+        assert_eq!(item.owner.as_deref(), Some("dan-lee"));
+    }
+
+    #[test]
+    fn email_addresses_are_not_mentions() {
+        let item = parse("Email bob@example.com");
+        assert_eq!(item.description, "Email bob@example.com");
+        assert_eq!(item.owner, None);
+    }
+
+    #[test]
+    fn person_at_the_start_becomes_the_owner() {
+        let item = parse("Anna Smith to book a room next week");
+        assert_eq!(item.description, "Book a room");
+        assert_eq!(item.owner.as_deref(), Some("Anna Smith"));
+        assert_eq!(item.due, Some(date(2026, 10, 26)));
+
+        let item = parse("bob: review PR by the end of the month.");
+        assert_eq!(item.description, "Review PR");
+        assert_eq!(item.owner.as_deref(), Some("Bob"));
+        assert_eq!(item.due, Some(date(2026, 10, 31)));
+
+        let item = parse("Bob will send the deck");
+        assert_eq!(item.description, "Send the deck");
+        assert_eq!(item.owner.as_deref(), Some("Bob"));
+    }
+
+    #[test]
+    fn person_mentioned_otherwise_is_not_the_owner() {
+        let item = parse("Anna Smith's deck needs review");
+        assert_eq!(item.description, "Anna Smith's deck needs review");
+        assert_eq!(item.owner, None);
+
+        let item = parse("Bobby to call back");
+        assert_eq!(item.owner, None);
+    }
+
+    #[test]
+    fn tasks_due_date_is_kept() {
+        let item = parse("Ship v2 by Friday 📅 2026-12-01");
+        assert_eq!(item.description, "Ship v2 by Friday");
+        assert_eq!(item.due, Some(date(2026, 12, 1)));
+    }
+
+    #[test]
+    fn render_puts_the_due_date_last() {
+        let item = parse("Anna Smith: send the deck by Friday");
+        assert_eq!(
+            item.render(Some("[[Sync]]")),
+            "Send the deck [owner:: Anna Smith] [[Sync]] 📅 2026-10-23"
+        );
+        assert_eq!(parse("Book room").render(None), "Book room");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::models::audio_note::AudioNote;
+use crate::obsidian::frontmatter::NoteDocument;
+use crate::utils::file_utils::markdown_files;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Which note in the vault belongs to which recording, read once so that
+/// checking many recordings doesn't read the whole vault for each of them.
+/// Notes are matched like `frontmatter::belongs_to` does: by `dropbox_id`, or
+/// by `audio_file_name` for notes written before ids were stored.
+#[derive(Debug, Default)]
+pub struct VaultIndex {
+    by_id: HashMap<String, PathBuf>,
+    by_name: HashMap<String, PathBuf>,
+}
+
+impl VaultIndex {
+    /// Indexes every note in `vault_path`, outside hidden folders such as
+    /// `.obsidian`.
+    pub fn build(vault_path: &Path) -> VaultIndex {
+        let mut index = VaultIndex::default();
+        for path in markdown_files(vault_path) {
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) => {
+                    eprintln!("Error reading file '{}': {}", path.display(), e);
+                    continue;
+                }
+            };
+            let (document, _) = NoteDocument::parse(&content);
+            if let Some(id) = document.get_str("dropbox_id") {
+                index.by_id.entry(id.to_string()).or_insert(path);
+                continue;
+            }
+            let name = document.get_str("audio_file_name").or_else(|| {
+                content
+                    .lines()
+                    .find_map(|line| line.trim().strip_prefix("audio_file_name: "))
+            });
+            if let Some(name) = name {
+                index.by_name.entry(name.to_string()).or_insert(path);
+            }
+        }
+        index
+    }
+
+    /// The note made from `audio_note`'s recording, if there is one.
+    pub fn find(&self, audio_note: &AudioNote) -> Option<&PathBuf> {
+        let metadata = &audio_note.audio_file_metadata;
+        self.by_id
+            .get(&metadata.id)
+            .or_else(|| self.by_name.get(&metadata.name))
+    }
+}
@@ -1,47 +1,88 @@
-use crate::config::config::Config;
-use reqwest::Client;
+use crate::config::config::{Config, GenerationSettings};
+use crate::error::error::SecretaryError;
+use crate::http::http::HttpClient;
+use crate::models::usage::Usage;
 use serde_json::json;
 
+/// A chat completion and the tokens it took.
+pub struct Completion {
+    pub content: String,
+    pub usage: Usage,
+}
+
 #[derive(Clone)]
 pub struct GptClient {
-    client: Client,
+    http: HttpClient,
     api_key: String,
 }
 
 impl GptClient {
-    pub fn new(config: &Config) -> Self {
-        let api_key = config.openai_api_key.clone();
-        let client = Client::new();
-        GptClient { client, api_key }
+    pub fn new(config: &Config, http: HttpClient) -> Self {
+        let api_key = config.openai.api_key.expose().to_string();
+        GptClient { http, api_key }
     }
 
+    /// Asks `model` to complete `prompt` with the parameters in `settings`.
     pub async fn fetch_completion(
-        &mut self,
+        &self,
         prompt: &str,
         model: &str,
-    ) -> Result<String, Box<dyn std::error::Error>> {
+        settings: &GenerationSettings,
+    ) -> Result<Completion, SecretaryError> {
         let url = "https://api.openai.com/v1/chat/completions";
 
-        let body = json!({
+        let mut messages = Vec::new();
+        if !settings.system_message.is_empty() {
+            messages.push(json!({"role": "system", "content": settings.system_message}));
+        }
+        messages.push(json!({"role": "user", "content": prompt}));
+        let mut body = json!({
             "model": model,
-            "messages": [{"role": "user", "content": prompt}],
-            "temperature": 0.7
+            "messages": messages,
+            "temperature": settings.temperature,
+            "top_p": settings.top_p
         });
+        if let Some(max_tokens) = settings.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(seed) = settings.seed {
+            body["seed"] = json!(seed);
+        }
 
         let response = self
-            .client
-            .post(url)
-            .bearer_auth(&self.api_key)
-            .json(&body)
-            .send()
+            .http
+            .send(|| {
+                self.http
+                    .client
+                    .post(url)
+                    .bearer_auth(&self.api_key)
+                    .json(&body)
+            })
             .await?;
+        if !response.status().is_success() {
+            return Err(SecretaryError::from_response("OpenAI", response)
+                .await
+                .for_key("openai.api_key"));
+        }
 
         let response_json: serde_json::Value = response.json().await?;
 
         println!("{:#?}", response_json);
-        response_json["choices"][0]["message"]["content"]
+        let content = response_json["choices"][0]["message"]["content"]
             .as_str()
             .map(str::to_string)
-            .ok_or_else(|| "Content not found in the response".into())
+            .ok_or_else(|| {
+                SecretaryError::Decode("Completion response has no message content".to_string())
+            })?;
+        let usage = Usage {
+            audio_minutes: 0.0,
+            prompt_tokens: response_json["usage"]["prompt_tokens"]
+                .as_u64()
+                .unwrap_or_default(),
+            completion_tokens: response_json["usage"]["completion_tokens"]
+                .as_u64()
+                .unwrap_or_default(),
+        };
+        Ok(Completion { content, usage })
     }
 }
@@ -0,0 +1,66 @@
+use crate::config::config::UsageConfig;
+use crate::models::dropbox_file_metadata::DropboxFileMetadata;
+use crate::models::usage::Usage;
+use crate::whisper::whisper::TRANSCRIPTION_MODEL;
+use std::path::Path;
+
+/// Tokens in a minute of transcribed speech (about 150 words).
+const TOKENS_PER_MINUTE: f64 = 200.0;
+/// Tokens in a typical generated note.
+const NOTE_TOKENS: u64 = 1000;
+
+/// A guess at what processing a recording costs, made before downloading it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Estimate {
+    pub audio_minutes: f64,
+    pub transcription_cost: f64,
+    pub analysis_cost: f64,
+}
+
+impl Estimate {
+    /// Estimates from the recording's size and the length of the prompt template
+    /// it will be analyzed with, at the configured prices of `model`.
+    pub fn new(
+        metadata: &DropboxFileMetadata,
+        prompt_chars: usize,
+        model: &str,
+        prices: &UsageConfig,
+    ) -> Self {
+        let audio_minutes =
+            metadata.size as f64 * 8.0 / (typical_bitrate_kbps(&metadata.name) * 1000.0) / 60.0;
+        let transcription = Usage {
+            audio_minutes,
+            ..Usage::default()
+        };
+        let analysis = Usage {
+            prompt_tokens: (prompt_chars as f64 / 4.0 + audio_minutes * TOKENS_PER_MINUTE) as u64,
+            completion_tokens: NOTE_TOKENS,
+            ..Usage::default()
+        };
+        Estimate {
+            audio_minutes,
+            transcription_cost: prices.cost(TRANSCRIPTION_MODEL, &transcription),
+            analysis_cost: prices.cost(model, &analysis),
+        }
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        self.transcription_cost + self.analysis_cost
+    }
+}
+
+/// Typical bitrate of a voice recording in each format, in kbit/s.
+fn typical_bitrate_kbps(name: &str) -> f64 {
+    let extension = Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "wav" => 1411.0,
+        "flac" => 700.0,
+        "mp3" | "mpga" | "mpeg" => 128.0,
+        "ogg" | "oga" | "webm" => 48.0,
+        // m4a and mp4, e.g. from iOS Voice Memos
+        _ => 64.0,
+    }
+}
@@ -0,0 +1,2 @@
+pub mod estimate;
+pub mod pipeline;
@@ -0,0 +1,395 @@
+use crate::cache::cache::AudioCache;
+use crate::config::config::{AnalysisConfig, ObsidianConfig, PipelineConfig, UsageConfig};
+use crate::dropbox::dropbox::{dropbox_content_hash, DropboxClient};
+use crate::error::error::SecretaryError;
+use crate::ledger::ledger::SharedLedger;
+use crate::models::audio_note::AudioNote;
+use crate::models::recording_status::RecordingStatus;
+use crate::models::usage::Usage;
+use crate::obsidian::attachment;
+use crate::obsidian::daily_note::DailyNotes;
+use crate::obsidian::frontmatter::NoteDocument;
+use crate::obsidian::regenerate::{managed_section, merge_note};
+use crate::obsidian::routing;
+use crate::obsidian::tasks::TaskFile;
+use crate::openai::gpt::GptClient;
+use crate::prompts::prompts::PromptLibrary;
+use crate::prompts::spoken_commands::SpokenCommands;
+use crate::whisper::whisper::{WhisperClient, TRANSCRIPTION_MODEL};
+use chrono::Utc;
+use similar::TextDiff;
+use std::path::Path;
+use std::sync::{Arc, PoisonError};
+use tokio::sync::Semaphore;
+
+/// Limits how many recordings are in each stage at once, across all tasks.
+pub struct StageLimits {
+    downloads: Semaphore,
+    transcriptions: Semaphore,
+    analyses: Semaphore,
+}
+
+impl StageLimits {
+    pub fn new(config: &PipelineConfig) -> Self {
+        StageLimits {
+            downloads: Semaphore::new(config.download_concurrency.max(1)),
+            transcriptions: Semaphore::new(config.transcription_concurrency.max(1)),
+            analyses: Semaphore::new(config.analysis_concurrency.max(1)),
+        }
+    }
+}
+
+/// Takes one recording through download, transcription, analysis and saving.
+/// Each recording runs in its own task, so a slow file only holds up its own
+/// stage slot, and its note is saved as soon as it is ready.
+#[derive(Clone)]
+pub struct Pipeline {
+    pub dropbox_client: DropboxClient,
+    pub whisper_client: WhisperClient,
+    pub gpt_client: GptClient,
+    pub ledger: SharedLedger,
+    pub obsidian: ObsidianConfig,
+    pub analysis: AnalysisConfig,
+    pub usage: UsageConfig,
+    pub cache: Arc<AudioCache>,
+    pub prompts: Arc<PromptLibrary>,
+    pub spoken_commands: Arc<SpokenCommands>,
+    pub daily_notes: Arc<DailyNotes>,
+    pub task_file: Arc<TaskFile>,
+    pub limits: Arc<StageLimits>,
+    /// Print the changes to the note instead of writing it.
+    pub dry_run: bool,
+}
+
+impl Pipeline {
+    /// Runs every stage for `audio_note`. On failure the note is recorded as
+    /// failed and returned along with the error.
+    pub async fn process(
+        &self,
+        mut audio_note: AudioNote,
+    ) -> (AudioNote, Result<(), SecretaryError>) {
+        let result = self.run_stages(&mut audio_note).await;
+        let mut ledger = self.ledger.lock().unwrap();
+        if let Err(error) = &result {
+            audio_note.status = RecordingStatus::Failed(error.to_string());
+            if let Err(error) = ledger.record(&audio_note) {
+                eprintln!("Failed to update the ledger: {}", error);
+            }
+        }
+        // Whatever was spent counts, even if the recording failed later on
+        if audio_note.usage != Usage::default() {
+            if let Err(error) = ledger.record_usage(&audio_note) {
+                eprintln!("Failed to record usage: {}", error);
+            }
+        }
+        drop(ledger);
+        (audio_note, result)
+    }
+
+    /// Runs `process` in a task of its own, so that a panic while processing
+    /// fails only this recording: it is recorded as failed instead of taking
+    /// down the service.
+    pub async fn process_isolated(
+        self,
+        audio_note: AudioNote,
+    ) -> (AudioNote, Result<(), SecretaryError>) {
+        let mut fallback = audio_note.clone();
+        let pipeline = self.clone();
+        match tokio::spawn(async move { pipeline.process(audio_note).await }).await {
+            Ok(processed) => processed,
+            Err(error) => {
+                let error = SecretaryError::Internal(format!("processing panicked: {}", error));
+                fallback.status = RecordingStatus::Failed(error.to_string());
+                // The panic may have happened while the ledger was locked
+                let mut ledger = self.ledger.lock().unwrap_or_else(PoisonError::into_inner);
+                if let Err(error) = ledger.record(&fallback) {
+                    eprintln!("Failed to update the ledger: {}", error);
+                }
+                drop(ledger);
+                (fallback, Err(error))
+            }
+        }
+    }
+
+    async fn run_stages(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
+        self.download(audio_note).await?;
+        self.transcribe(audio_note).await?;
+        self.choose_profile(audio_note).await;
+        self.analyze(audio_note).await?;
+        if !self.dry_run {
+            audio_note.audio_embed =
+                attachment::attach_audio(&self.obsidian, &self.dropbox_client, audio_note).await?;
+        }
+        self.clean(audio_note)?;
+        self.save(audio_note).await
+    }
+
+    /// Downloads the recording into the cache directory, unless an intact copy
+    /// is already there.
+    async fn download(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
+        let _permit = self.limits.downloads.acquire().await.unwrap();
+        tokio::fs::create_dir_all(self.cache.dir()).await?;
+        let cache_path = self.cache.path_for(&audio_note.audio_file_metadata);
+        let metadata = &audio_note.audio_file_metadata;
+
+        let cached = match tokio::fs::metadata(&cache_path).await {
+            Ok(file) => match &metadata.content_hash {
+                Some(content_hash) => dropbox_content_hash(&cache_path).await? == *content_hash,
+                None => file.len() == metadata.size,
+            },
+            Err(_) => false,
+        };
+        if cached {
+            println!("Using cached audio file: {}", cache_path.display());
+        } else {
+            self.dropbox_client
+                .download_file(
+                    &metadata.path_lower,
+                    &cache_path,
+                    metadata.content_hash.as_deref(),
+                )
+                .await?;
+        }
+
+        audio_note.local_audio_file_path = cache_path;
+        self.set_status(audio_note, RecordingStatus::Downloaded)
+    }
+
+    async fn transcribe(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
+        let _permit = self.limits.transcriptions.acquire().await.unwrap();
+        self.whisper_client.transcribe_file(audio_note).await?;
+        if let Some(duration) = audio_note.duration_seconds {
+            let usage = Usage {
+                audio_minutes: duration / 60.0,
+                ..Usage::default()
+            };
+            audio_note.usage += usage;
+            audio_note.cost += self.usage.cost(TRANSCRIPTION_MODEL, &usage);
+        }
+        self.set_status(audio_note, RecordingStatus::Transcribed)
+    }
+
+    /// Applies the spoken command the recording starts with, if any: its
+    /// profile, folder and tags, with the command cut from the transcription.
+    /// Without one, the profile rules decide, now that keyword rules can be
+    /// checked. A profile picked by the user is kept either way.
+    async fn choose_profile(&self, audio_note: &mut AudioNote) {
+        let name = audio_note.audio_file_metadata.name.clone();
+        let mut command =
+            self.spoken_commands
+                .detect(&audio_note.transcription)
+                .map(|(command, rest)| {
+                    audio_note.transcription = rest;
+                    command
+                });
+        if command.is_none() && self.spoken_commands.classifies() {
+            let settings = self.analysis.settings_for(&audio_note.prompt_profile);
+            match self
+                .spoken_commands
+                .classify(&self.gpt_client, &settings, &audio_note.transcription)
+                .await
+            {
+                Ok((classified, usage)) => {
+                    audio_note.usage += usage;
+                    audio_note.cost += self.usage.cost(&settings.model, &usage);
+                    command = classified;
+                }
+                Err(error) => eprintln!("Failed to classify {}: {}", name, error),
+            }
+        }
+
+        if let Some(command) = command {
+            println!("{}: spoken command '{}'", name, command.phrase);
+            audio_note.folder = command.folder.clone();
+            audio_note.tags = command.tags.clone();
+            if !command.profile.is_empty() && !audio_note.profile_requested {
+                audio_note.prompt_profile = command.profile.clone();
+                return;
+            }
+        }
+        if !audio_note.profile_requested {
+            audio_note.prompt_profile = self.prompts.select(
+                &audio_note.audio_file_metadata,
+                Some(&audio_note.transcription),
+            );
+        }
+    }
+
+    // TODO: Implement the multi call system with function calling.
+    // TODO: Add support for assistant id.
+    /// Has the profile's model write the note, falling back to the next model
+    /// in its chain when one fails.
+    async fn analyze(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
+        let _permit = self.limits.analyses.acquire().await.unwrap();
+        println!("{}", audio_note);
+        let prompt = self.prompts.render(audio_note, &self.obsidian.vault_path)?;
+        let settings = self.analysis.settings_for(&audio_note.prompt_profile);
+
+        let models = settings.models();
+        let mut models = models.iter().peekable();
+        let completion = loop {
+            let model = models.next().expect("the model chain is never empty");
+            match self
+                .gpt_client
+                .fetch_completion(&prompt, model, &settings)
+                .await
+            {
+                Ok(completion) => {
+                    audio_note.model = model.to_string();
+                    break completion;
+                }
+                Err(error) => match models.peek() {
+                    Some(next) if can_fall_back(&error) => {
+                        eprintln!("{} failed ({}), trying {}", model, error, next);
+                    }
+                    _ => return Err(error),
+                },
+            }
+        };
+
+        audio_note.note = completion.content;
+        audio_note.usage += completion.usage;
+        audio_note.cost += self.usage.cost(&audio_note.model, &completion.usage);
+        self.set_status(audio_note, RecordingStatus::Analyzed)
+    }
+
+    /// Merges Secretary's frontmatter keys into the model's note, picks the
+    /// note's folder, and puts the recording's embed at the top and the
+    /// transcription at the end, each in its own managed section.
+    fn clean(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
+        let (mut document, problem) = NoteDocument::parse(&audio_note.note);
+        if let Some(problem) = problem {
+            eprintln!("{}: {}", audio_note.audio_file_metadata.name, problem);
+        }
+        document.merge_secretary_keys(
+            audio_note,
+            &Utc::now().to_rfc3339(),
+            self.usage.write_to_frontmatter,
+        );
+        document.add_tags(&audio_note.tags);
+        // A spoken command's folder wins over the routes
+        let template = match audio_note.folder.as_str() {
+            "" => routing::route(&self.obsidian.routes, &document, audio_note).unwrap_or(""),
+            folder => folder,
+        };
+        audio_note.folder = routing::expand_folder(template, &document, audio_note);
+
+        let mut body = String::new();
+        if let Some(audio_embed) = &audio_note.audio_embed {
+            body.push_str(&managed_section("audio", audio_embed));
+        }
+        body.push_str(&managed_section("analysis", &document.body));
+        body.push('\n');
+        body.push_str(&managed_section(
+            "transcription",
+            &format!("## Transcription\n{}", audio_note.transcription),
+        ));
+        document.body = body;
+        audio_note.note = document.render()?;
+        Ok(())
+    }
+
+    /// Writes the note to the vault. A recording that already has a note, e.g.
+    /// when reprocessing, gets the managed parts of it regenerated instead.
+    async fn save(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
+        audio_note.make_note_name_from_title().await?;
+        let vault_path = &self.obsidian.vault_path;
+        let folder = Path::new(vault_path).join(&audio_note.folder);
+
+        if let Some(existing_path) = audio_note.find_existing_note(Path::new(vault_path)) {
+            let existing = tokio::fs::read_to_string(&existing_path).await?;
+            let merged = merge_note(&existing, &audio_note.note)?;
+            audio_note.note_path = existing_path;
+            if self.dry_run {
+                let path = audio_note.note_path.display().to_string();
+                print!(
+                    "{}",
+                    TextDiff::from_lines(&existing, &merged)
+                        .unified_diff()
+                        .header(&path, &path)
+                );
+            } else {
+                audio_note.note = merged;
+            }
+        } else if self.dry_run {
+            println!(
+                "Would create a note named '{}' in {}",
+                audio_note.expand_name_pattern(&self.obsidian.note_name_pattern),
+                folder.display()
+            );
+        } else {
+            tokio::fs::create_dir_all(&folder).await?;
+            audio_note
+                .make_note_path(&folder, &self.obsidian.note_name_pattern)
+                .await?;
+        }
+
+        if self.dry_run {
+            if self.daily_notes.enabled() {
+                println!(
+                    "Would add it to the daily note {}",
+                    self.daily_notes.path_for(audio_note).display()
+                );
+            }
+            if self.task_file.enabled() {
+                println!(
+                    "Would add its action items to {}",
+                    self.task_file.path().display()
+                );
+            }
+            return Ok(());
+        }
+
+        // Asynchronously write the note content to the file
+        let file_path = &audio_note.note_path;
+        tokio::fs::write(file_path, &audio_note.note).await?;
+        println!("Note saved: {}", file_path.display());
+        if self.daily_notes.enabled() {
+            // The note is saved either way, so this doesn't fail the recording
+            match self.daily_notes.add_entry(audio_note) {
+                Ok(path) => println!("Added to daily note: {}", path.display()),
+                Err(error) => eprintln!(
+                    "Failed to update the daily note for {}: {}",
+                    audio_note.note_name, error
+                ),
+            }
+        }
+        if self.task_file.enabled() {
+            match self.task_file.add_items(audio_note) {
+                Ok(0) => {}
+                Ok(count) => println!(
+                    "Added {} action items to {}",
+                    count,
+                    self.task_file.path().display()
+                ),
+                Err(error) => eprintln!(
+                    "Failed to update the task file for {}: {}",
+                    audio_note.note_name, error
+                ),
+            }
+        }
+        self.set_status(audio_note, RecordingStatus::Saved)?;
+        self.cache.release(&audio_note.local_audio_file_path)
+    }
+
+    fn set_status(
+        &self,
+        audio_note: &mut AudioNote,
+        status: RecordingStatus,
+    ) -> Result<(), SecretaryError> {
+        audio_note.status = status;
+        self.ledger.lock().unwrap().record(audio_note)
+    }
+}
+
+/// Whether another model might succeed where one failed. Network, request,
+/// authorization and API key problems affect every model alike.
+fn can_fall_back(error: &SecretaryError) -> bool {
+    !matches!(
+        error,
+        SecretaryError::Network(_)
+            | SecretaryError::Request(_)
+            | SecretaryError::AuthExpired { .. }
+            | SecretaryError::Config(_)
+    )
+}
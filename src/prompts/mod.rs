@@ -0,0 +1,2 @@
+pub mod prompts;
+pub mod spoken_commands;
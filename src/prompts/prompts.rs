@@ -0,0 +1,255 @@
+use crate::config::config::{Config, PromptsConfig};
+use crate::error::error::SecretaryError;
+use crate::models::audio_note::AudioNote;
+use crate::models::dropbox_file_metadata::DropboxFileMetadata;
+use crate::utils::file_utils::{markdown_files, matches_wildcard};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Placeholders a prompt template may use, e.g. `{transcription}`.
+pub const PLACEHOLDERS: [&str; 7] = [
+    "transcription",
+    "file_name",
+    "date",
+    "duration",
+    "language",
+    "speakers",
+    "vault_context",
+];
+
+/// Where the default profile's template used to live, next to the config.
+const LEGACY_DEFAULT_PROMPT: &str = "prompt.md";
+
+/// The prompt templates of every profile, loaded and checked once at startup.
+pub struct PromptLibrary {
+    config: PromptsConfig,
+    templates: BTreeMap<String, String>,
+}
+
+impl PromptLibrary {
+    /// Loads every `<profile>.md` in `prompts.dir`. The default profile may also
+    /// come from `prompt.md` in the working directory. Fails on templates with
+    /// unknown placeholders or without `{transcription}`, and on profiles named
+    /// in the config that have no template.
+    pub fn load(config: &Config) -> Result<Self, SecretaryError> {
+        let prompts = &config.prompts;
+        let mut templates = BTreeMap::new();
+        if let Ok(entries) = fs::read_dir(&prompts.dir) {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().and_then(std::ffi::OsStr::to_str) != Some("md") {
+                    continue;
+                }
+                if let Some(name) = path.file_stem().and_then(std::ffi::OsStr::to_str) {
+                    templates.insert(name.to_string(), fs::read_to_string(&path)?);
+                }
+            }
+        }
+        if !templates.contains_key(&prompts.default_profile)
+            && Path::new(LEGACY_DEFAULT_PROMPT).is_file()
+        {
+            templates.insert(
+                prompts.default_profile.clone(),
+                fs::read_to_string(LEGACY_DEFAULT_PROMPT)?,
+            );
+        }
+
+        let library = PromptLibrary {
+            config: prompts.clone(),
+            templates,
+        };
+        library.validate(config)?;
+        println!("Loaded prompt profiles: {}", library.profiles().join(", "));
+        Ok(library)
+    }
+
+    fn validate(&self, config: &Config) -> Result<(), SecretaryError> {
+        let mut problems = Vec::new();
+        for (name, template) in &self.templates {
+            if !template.contains("{transcription}") {
+                problems.push(format!("prompt '{}' has no {{transcription}}", name));
+            }
+            for placeholder in placeholders(template) {
+                if !PLACEHOLDERS.contains(&placeholder) {
+                    problems.push(format!(
+                        "prompt '{}' uses unknown placeholder {{{}}}",
+                        name, placeholder
+                    ));
+                }
+            }
+        }
+
+        let mut referenced = vec![("prompts.default_profile", &self.config.default_profile)];
+        referenced.extend(
+            self.config
+                .rules
+                .iter()
+                .map(|rule| ("prompts.rules", &rule.profile)),
+        );
+        referenced.extend(
+            config
+                .spoken_commands
+                .commands
+                .iter()
+                .filter(|command| !command.profile.is_empty())
+                .map(|command| ("spoken_commands.commands", &command.profile)),
+        );
+        referenced.extend(
+            config
+                .analysis
+                .profiles
+                .keys()
+                .map(|name| ("analysis.profiles", name)),
+        );
+        for (key, name) in referenced {
+            if !self.templates.contains_key(name) {
+                problems.push(format!(
+                    "{} names profile '{}', but there is no {}",
+                    key,
+                    name,
+                    Path::new(&self.config.dir)
+                        .join(format!("{}.md", name))
+                        .display()
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(SecretaryError::Config(format!(
+                "invalid prompts:\n  - {}",
+                problems.join("\n  - ")
+            )))
+        }
+    }
+
+    /// Names of all profiles, the default one first.
+    pub fn profiles(&self) -> Vec<String> {
+        let mut profiles = vec![self.config.default_profile.clone()];
+        profiles.extend(
+            self.templates
+                .keys()
+                .filter(|name| **name != self.config.default_profile)
+                .cloned(),
+        );
+        profiles
+    }
+
+    pub fn contains(&self, profile: &str) -> bool {
+        self.templates.contains_key(profile)
+    }
+
+    pub fn template(&self, profile: &str) -> Option<&str> {
+        self.templates.get(profile).map(String::as_str)
+    }
+
+    /// The profile for a recording: that of the first rule it matches, or the
+    /// default. Rules with a keyword only match once the `transcription` is known.
+    pub fn select(&self, metadata: &DropboxFileMetadata, transcription: Option<&str>) -> String {
+        let folder = metadata.folder();
+        self.config
+            .rules
+            .iter()
+            .find(|rule| {
+                (rule.folder.is_empty()
+                    || folder == rule.folder.to_lowercase().trim_end_matches('/'))
+                    && (rule.file_pattern.is_empty()
+                        || matches_wildcard(&rule.file_pattern, &metadata.name))
+                    && (rule.keyword.is_empty()
+                        || transcription
+                            .is_some_and(|text| starts_with_phrase(text, &rule.keyword)))
+            })
+            .map(|rule| rule.profile.clone())
+            .unwrap_or_else(|| self.config.default_profile.clone())
+    }
+
+    /// The prompt for `audio_note`, from its profile's template.
+    pub fn render(
+        &self,
+        audio_note: &AudioNote,
+        vault_path: &str,
+    ) -> Result<String, SecretaryError> {
+        let template = self.template(&audio_note.prompt_profile).ok_or_else(|| {
+            SecretaryError::Config(format!(
+                "unknown prompt profile '{}'",
+                audio_note.prompt_profile
+            ))
+        })?;
+        let metadata = &audio_note.audio_file_metadata;
+        let duration = match audio_note.duration_seconds {
+            Some(seconds) => format!("{}:{:02}", seconds as u64 / 60, seconds as u64 % 60),
+            None => "unknown".to_string(),
+        };
+        let speakers = if self.config.speakers.is_empty() {
+            "unknown".to_string()
+        } else {
+            self.config.speakers.join(", ")
+        };
+        let mut prompt = template
+            .replace("{file_name}", &metadata.name)
+            .replace(
+                "{date}",
+                metadata.client_modified.get(..10).unwrap_or_default(),
+            )
+            .replace("{duration}", &duration)
+            .replace(
+                "{language}",
+                audio_note.language.as_deref().unwrap_or("unknown"),
+            )
+            .replace("{speakers}", &speakers);
+        if prompt.contains("{vault_context}") {
+            prompt = prompt.replace(
+                "{vault_context}",
+                &vault_context(vault_path, self.config.vault_context_notes),
+            );
+        }
+        // Last, so placeholders spoken in the recording stay as they are
+        Ok(prompt.replace("{transcription}", &audio_note.transcription))
+    }
+}
+
+/// Whether `text` starts with the words of `phrase`, ignoring case and punctuation.
+pub fn starts_with_phrase(text: &str, phrase: &str) -> bool {
+    let words = |text: &str| -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+    let phrase = words(phrase);
+    !phrase.is_empty() && words(text).starts_with(&phrase)
+}
+
+/// The `{name}` placeholders in `template`, skipping braces around anything
+/// but a lowercase identifier (e.g. JSON examples).
+fn placeholders(template: &str) -> Vec<&str> {
+    template
+        .split('{')
+        .skip(1)
+        .filter_map(|rest| rest.split_once('}').map(|(name, _)| name))
+        .filter(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+        .collect()
+}
+
+/// The most recently changed notes in the vault, as a list of links, so the
+/// model can link to them.
+fn vault_context(vault_path: &str, limit: usize) -> String {
+    let mut notes: Vec<(SystemTime, String)> = markdown_files(Path::new(vault_path))
+        .into_iter()
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).ok()?.modified().ok()?;
+            Some((modified, path.file_stem()?.to_string_lossy().to_string()))
+        })
+        .collect();
+    notes.sort_by_key(|(modified, _)| Reverse(*modified));
+    notes
+        .into_iter()
+        .take(limit)
+        .map(|(_, name)| format!("- [[{}]]", name))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,118 @@
+use crate::config::config::{GenerationSettings, SpokenCommand, SpokenCommandsConfig};
+use crate::error::error::SecretaryError;
+use crate::models::usage::Usage;
+use crate::openai::gpt::GptClient;
+use crate::prompts::prompts::starts_with_phrase;
+
+/// How much of the transcription the model sees when classifying, in words.
+const CLASSIFY_WORDS: usize = 40;
+
+/// Commands a recording may start with, e.g. "Meeting notes. We talked about…",
+/// that choose how it is processed.
+pub struct SpokenCommands {
+    config: SpokenCommandsConfig,
+}
+
+impl SpokenCommands {
+    pub fn new(config: &SpokenCommandsConfig) -> Self {
+        SpokenCommands {
+            config: config.clone(),
+        }
+    }
+
+    /// Whether recordings without a command phrase are classified by the model.
+    pub fn classifies(&self) -> bool {
+        self.config.classify_with_llm && !self.config.commands.is_empty()
+    }
+
+    /// The command whose phrase `transcription` starts with (the longest, if
+    /// several do) and the transcription with the phrase cut off.
+    pub fn detect(&self, transcription: &str) -> Option<(&SpokenCommand, String)> {
+        self.config
+            .commands
+            .iter()
+            .filter_map(|command| {
+                strip_phrase(transcription, &command.phrase).map(|rest| (command, rest))
+            })
+            .min_by_key(|(_, rest)| rest.len())
+    }
+
+    /// Asks the model whether the recording starts with one of the commands in
+    /// other words than its phrase. As those words aren't known, nothing is cut
+    /// from the transcription. Also returns what the question consumed.
+    pub async fn classify(
+        &self,
+        gpt_client: &GptClient,
+        settings: &GenerationSettings,
+        transcription: &str,
+    ) -> Result<(Option<&SpokenCommand>, Usage), SecretaryError> {
+        let commands = self
+            .config
+            .commands
+            .iter()
+            .map(|command| format!("- {}", command.phrase))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let opening = transcription
+            .split_whitespace()
+            .take(CLASSIFY_WORDS)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let prompt = format!(
+            "A voice memo may start with a spoken command saying what kind of recording it is. \
+             The commands are:\n{}\n\nThe memo starts like this:\n\n{}\n\n\
+             If it starts with one of these commands, possibly in other words, answer with \
+             that command exactly as listed. Otherwise answer \"none\".",
+            commands, opening
+        );
+        let settings = GenerationSettings {
+            temperature: 0.0,
+            max_tokens: Some(20),
+            system_message: "".to_string(),
+            ..settings.clone()
+        };
+
+        let completion = gpt_client
+            .fetch_completion(&prompt, &settings.model, &settings)
+            .await?;
+        let command = self
+            .config
+            .commands
+            .iter()
+            .filter(|command| starts_with_phrase(&completion.content, &command.phrase))
+            .max_by_key(|command| command.phrase.len());
+        Ok((command, completion.usage))
+    }
+}
+
+/// `text` without the words of `phrase` at its start and the punctuation after
+/// them, or `None` if it doesn't start with them. Case and punctuation between
+/// the words are ignored.
+fn strip_phrase(text: &str, phrase: &str) -> Option<String> {
+    let mut words = phrase
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .peekable();
+    words.peek()?;
+
+    let mut rest = text;
+    for word in words {
+        rest = rest.trim_start_matches(|c: char| !c.is_alphanumeric());
+        let end = rest
+            .find(|c: char| !c.is_alphanumeric())
+            .unwrap_or(rest.len());
+        if rest[..end].to_lowercase() != word.to_lowercase() {
+            return None;
+        }
+        rest = &rest[end..];
+    }
+
+    let rest = rest.trim_start_matches(|c: char| {
+        c.is_whitespace() || matches!(c, ',' | '.' | ':' | ';' | '!' | '?' | '-' | '–' | '—')
+    });
+    let mut chars = rest.chars();
+    Some(match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => "".to_string(),
+    })
+}
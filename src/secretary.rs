@@ -1,14 +1,28 @@
 // TODO: Support specifying the assistant ID as a parameter to process_transcriptions(). [[5]](https://poe.com/citation?message_id=175047583702&citation=5)
-// TODO: Make the model array a constant at the top of the file. [[5]](https://poe.com/citation?message_id=175047583702&citation=5)
-// TODO: Load the prompt template at startup rather than on each iteration. [[5]](https://poe.com/citation?message_id=175047583702&citation=5)
-// TODO: Provide more informative error messages on failure. [[5]](https://poe.com/citation?message_id=175047583702&citation=5)
+use crate::auth::auth::PendingAuthCode;
+use crate::cache::cache::AudioCache;
 use crate::config::config::Config;
+use crate::dashboard::dashboard::ReprocessRequest;
 use crate::dropbox::dropbox::DropboxClient;
-use crate::models::audio_note::{self, AudioNote};
+use crate::error::error::SecretaryError;
+use crate::http::http::HttpClients;
+use crate::ledger::ledger::{Ledger, SharedLedger};
+use crate::models::audio_note::AudioNote;
 use crate::models::dropbox_file_metadata::DropboxFileMetadata;
+use crate::models::recording_status::RecordingStatus;
+use crate::obsidian::daily_note::DailyNotes;
+use crate::obsidian::tasks::TaskFile;
 use crate::openai::gpt::GptClient;
+use crate::pipeline::estimate::Estimate;
+use crate::pipeline::pipeline::{Pipeline, StageLimits};
+use crate::prompts::prompts::PromptLibrary;
+use crate::prompts::spoken_commands::SpokenCommands;
 use crate::whisper::whisper::WhisperClient;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinSet;
 
 #[derive(Clone)]
 pub struct Secretary {
@@ -16,24 +30,107 @@ pub struct Secretary {
     pub dropbox_client: DropboxClient,
     pub whisper_client: WhisperClient,
     pub gpt_client: GptClient,
+    /// Recordings waiting to be processed, at most one per Dropbox file id.
+    /// Notes leave the queue once processed; only those that failed with a
+    /// retryable error come back.
     pub audio_notes: Vec<AudioNote>,
+    pub ledger: SharedLedger,
+    pub pending_auth_code: PendingAuthCode,
+    pub stage_limits: Arc<StageLimits>,
+    pub cache: Arc<AudioCache>,
+    pub prompts: Arc<PromptLibrary>,
+    pub spoken_commands: Arc<SpokenCommands>,
+    pub daily_notes: Arc<DailyNotes>,
+    pub task_file: Arc<TaskFile>,
 }
 
 impl Secretary {
-    pub fn new(config: Config) -> Secretary {
-        let dropbox_client = DropboxClient::new(&config);
-        let whisper_client = WhisperClient::new(&config);
-        let gpt_client = GptClient::new(&config);
-        Secretary {
+    pub fn new(config: Config) -> Result<Secretary, SecretaryError> {
+        let http = HttpClients::new(&config.http)?;
+        let dropbox_client = DropboxClient::new(&config, http.dropbox.clone());
+        let whisper_client = WhisperClient::new(&config, http.openai.clone());
+        let gpt_client = GptClient::new(&config, http.openai);
+        let mut ledger = Ledger::load(&config.storage.ledger_path)?;
+        if config.dry_run {
+            ledger.set_read_only();
+        }
+        let ledger = Arc::new(Mutex::new(ledger));
+        let stage_limits = Arc::new(StageLimits::new(&config.pipeline));
+        let cache = Arc::new(AudioCache::new(&config.storage));
+        let prompts = Arc::new(PromptLibrary::load(&config)?);
+        let spoken_commands = Arc::new(SpokenCommands::new(&config.spoken_commands));
+        let daily_notes = Arc::new(DailyNotes::new(
+            &config.daily_notes,
+            &config.obsidian.vault_path,
+        ));
+        let task_file = Arc::new(TaskFile::new(&config.tasks, &config.obsidian.vault_path));
+        Ok(Secretary {
             config,
             dropbox_client,
             whisper_client,
             gpt_client,
             audio_notes: Vec::new(),
+            ledger,
+            pending_auth_code: Arc::new(Mutex::new(None)),
+            stage_limits,
+            cache,
+            prompts,
+            spoken_commands,
+            daily_notes,
+            task_file,
+        })
+    }
+
+    /// Queues a recording from the ledger to run through the pipeline again with
+    /// another prompt profile. Its existing note is regenerated in place.
+    pub fn queue_reprocess(&mut self, request: ReprocessRequest) -> Result<(), SecretaryError> {
+        if !self.prompts.contains(&request.profile) {
+            return Err(SecretaryError::Usage(format!(
+                "Unknown prompt profile '{}'",
+                request.profile
+            )));
         }
+        let (metadata, note_path) = match self.ledger.lock().unwrap().get(&request.id) {
+            Some(entry) => (entry.audio_file_metadata.clone(), entry.note_path.clone()),
+            None => {
+                return Err(SecretaryError::Usage(format!(
+                    "Unknown recording id: {}",
+                    request.id
+                )))
+            }
+        };
+        println!(
+            "Reprocessing {} with profile '{}'",
+            metadata.name, request.profile
+        );
+        let mut audio_note = AudioNote::new_from_metadata(&metadata);
+        audio_note.prompt_profile = request.profile;
+        audio_note.profile_requested = true;
+        audio_note.note_path = note_path.unwrap_or_default();
+        self.ledger.lock().unwrap().record(&audio_note)?;
+        // A newer request replaces one for the same recording that is still queued
+        self.audio_notes
+            .retain(|queued| queued.audio_file_metadata.id != metadata.id);
+        self.audio_notes.push(audio_note);
+        Ok(())
     }
 
-    pub async fn update_audio_notes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn is_queued(&self, id: &str) -> bool {
+        self.audio_notes
+            .iter()
+            .any(|queued| queued.audio_file_metadata.id == id)
+    }
+
+    /// Whether the ledger gave up on this recording. It then stays out of the
+    /// queue until it is reprocessed from the dashboard.
+    fn is_dead_lettered(&self, id: &str) -> bool {
+        matches!(
+            self.ledger.lock().unwrap().get(id),
+            Some(entry) if matches!(entry.status, RecordingStatus::Failed(_))
+        )
+    }
+
+    pub async fn update_audio_notes(&mut self) -> Result<(), SecretaryError> {
         let files_metadata = self.dropbox_client.list_files().await?;
         let extensions = DropboxClient::audio_file_extensions();
         let audio_files_metadata: Vec<DropboxFileMetadata> = files_metadata
@@ -45,85 +142,214 @@ impl Secretary {
             })
             .collect();
 
+        let vault_path = PathBuf::from(&self.config.obsidian.vault_path);
+        if let Err(e) = fs::read_dir(&vault_path) {
+            eprintln!("Failed to read directory: {}", e);
+            return Ok(());
+        }
+        let mut vault_index = None;
         for metadata in audio_files_metadata {
+            if self.is_queued(&metadata.id) || self.is_dead_lettered(&metadata.id) {
+                continue;
+            }
             let mut audio_note = AudioNote::new_from_metadata(&metadata);
-            if audio_note.check_if_new_file(&PathBuf::from(&self.config.obsidian_vault_path)) {
+            audio_note.prompt_profile = self.prompts.select(&metadata, None);
+            // Notes may be anywhere in the vault; look where it was saved first
+            if let Some(note_path) = self
+                .ledger
+                .lock()
+                .unwrap()
+                .get(&metadata.id)
+                .and_then(|entry| entry.note_path.clone())
+            {
+                audio_note.note_path = note_path;
+            }
+            if audio_note.check_if_new_file(&mut vault_index, &vault_path) {
+                self.ledger.lock().unwrap().record(&audio_note)?;
                 self.audio_notes.push(audio_note);
             }
         }
         Ok(())
     }
 
-    pub async fn download_audio_files(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for audio_note in self.audio_notes.iter_mut() {
-            let audio_note_bytes = self
-                .dropbox_client
-                .download_file(&audio_note.audio_file_metadata.path_lower)
-                .await?;
-            audio_note::AudioNote::save_audio_file(audio_note, audio_note_bytes).await?;
+    /// Runs every queued recording through the pipeline concurrently, within the
+    /// per-stage limits, and empties the queue. Recordings that failed with a
+    /// retryable error or need re-authorization are queued again and the first
+    /// such error is returned (preferring re-authorization); any other failure
+    /// is dead-lettered in the ledger. Once the monthly budget is spent, the
+    /// queue is left as it is.
+    pub async fn process_audio_notes(&mut self) -> Result<(), SecretaryError> {
+        let budget = self.config.usage.monthly_budget;
+        if budget > 0.0 && !self.audio_notes.is_empty() {
+            let spent = self.ledger.lock().unwrap().cost_this_month();
+            if spent >= budget {
+                println!(
+                    "Monthly budget of ${:.2} reached (${:.2} spent). {} recordings wait until next month.",
+                    budget,
+                    spent,
+                    self.audio_notes.len()
+                );
+                return Ok(());
+            }
         }
-        Ok(())
-    }
 
-    pub async fn transcribe_audio_files(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for audio_note in self.audio_notes.iter_mut() {
-            self.whisper_client.transcribe_file(audio_note).await?;
+        let pipeline = Pipeline {
+            dropbox_client: self.dropbox_client.clone(),
+            whisper_client: self.whisper_client.clone(),
+            gpt_client: self.gpt_client.clone(),
+            ledger: self.ledger.clone(),
+            obsidian: self.config.obsidian.clone(),
+            analysis: self.config.analysis.clone(),
+            usage: self.config.usage.clone(),
+            cache: self.cache.clone(),
+            prompts: self.prompts.clone(),
+            spoken_commands: self.spoken_commands.clone(),
+            daily_notes: self.daily_notes.clone(),
+            task_file: self.task_file.clone(),
+            limits: self.stage_limits.clone(),
+            dry_run: self.config.dry_run,
+        };
+
+        let mut tasks = JoinSet::new();
+        for audio_note in std::mem::take(&mut self.audio_notes) {
+            let pipeline = pipeline.clone();
+            tasks.spawn(pipeline.process_isolated(audio_note));
         }
-        Ok(())
-    }
-    // TODO: Implement the multi call system with function calling.
-    // TODO: Add support for assistant id.
-    pub async fn process_transcriptions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let model: [&str; 3] = ["gpt-3.5-turbo", "gpt-4", "gpt-4o"];
-        let prompt_template = std::fs::read_to_string("prompt.md")?;
-        for audio_note in self.audio_notes.iter_mut() {
-            println!("{}", audio_note);
-            let prompt = prompt_template.replace("{transcription}", &audio_note.transcription);
-            audio_note.note = self.gpt_client.fetch_completion(&prompt, model[2]).await?;
+
+        let mut first_error: Option<SecretaryError> = None;
+        while let Some(joined) = tasks.join_next().await {
+            // Panics are caught per recording; this is only reached on shutdown
+            let (audio_note, result) = match joined {
+                Ok(processed) => processed,
+                Err(error) => {
+                    eprintln!("A pipeline task failed: {}", error);
+                    continue;
+                }
+            };
+            let Err(error) = result else {
+                continue;
+            };
+            if !(error.is_retryable() || error.requires_reauth()) {
+                eprintln!(
+                    "Giving up on {}: {}",
+                    audio_note.audio_file_metadata.name, error
+                );
+                continue;
+            }
+
+            eprintln!(
+                "Failed to process {}, will retry: {}",
+                audio_note.audio_file_metadata.name, error
+            );
+            let replace = match &first_error {
+                None => true,
+                Some(first) => error.requires_reauth() && !first.requires_reauth(),
+            };
+            if replace {
+                first_error = Some(error);
+            }
+            // Start over, but keep the profile; downloads are cached anyway
+            let mut retry = AudioNote::new_from_metadata(&audio_note.audio_file_metadata);
+            retry.prompt_profile = audio_note.prompt_profile;
+            retry.profile_requested = audio_note.profile_requested;
+            self.ledger.lock().unwrap().record(&retry)?;
+            self.audio_notes.push(retry);
         }
 
-        Ok(())
+        match first_error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 
-    pub async fn clean_notes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for audio_note in self.audio_notes.iter_mut() {
-            let audio_file_name = &audio_note.audio_file_metadata.name;
+    /// Prints what the next check would do: the new recordings, their estimated
+    /// length and cost, and where their notes would go. Nothing is downloaded,
+    /// sent to OpenAI or written to the vault.
+    pub async fn print_plan(&mut self) -> Result<(), SecretaryError> {
+        self.update_audio_notes().await?;
+        if self.audio_notes.is_empty() {
+            println!("Dry run: no new recordings.");
+            return Ok(());
+        }
+        println!("Dry run: {} new recordings", self.audio_notes.len());
 
-            // Split the note into two parts and add audio file name
-            if let Some((part1, part2)) = audio_note.note.split_once("---") {
-                audio_note.note = format!(
-                    "{}---\naudio_file_name: {}\n{}",
-                    part1, audio_file_name, part2
-                );
+        let obsidian = &self.config.obsidian;
+        let unknown_title = obsidian.note_name_pattern.contains("{title}");
+        let mut planned = HashSet::new();
+        let mut total = Estimate::default();
+        for audio_note in &mut self.audio_notes {
+            let prompt = self
+                .prompts
+                .template(&audio_note.prompt_profile)
+                .unwrap_or_default();
+            let estimate = Estimate::new(
+                &audio_note.audio_file_metadata,
+                prompt.len(),
+                &self
+                    .config
+                    .analysis
+                    .settings_for(&audio_note.prompt_profile)
+                    .model,
+                &self.config.usage,
+            );
+            total.audio_minutes += estimate.audio_minutes;
+            total.transcription_cost += estimate.transcription_cost;
+            total.analysis_cost += estimate.analysis_cost;
+
+            // The title is only known after the analysis
+            audio_note.note_name = "{title}".to_string();
+            let (note_path, collision) = audio_note.plan_note_path(
+                Path::new(&obsidian.vault_path),
+                &obsidian.note_name_pattern,
+                &planned,
+            );
+            let remark = if collision {
+                " (name already taken, suffix added)"
+            } else if unknown_title {
+                " ({title} from the analysis)"
             } else {
-                // If the split was unsuccessful, add audio file name at the start
-                audio_note.note = format!(
-                    "{}\naudio_file_name: {}\n",
-                    audio_note.note, audio_file_name
-                );
-            }
+                ""
+            };
 
-            // Append the transcription at the end
-            audio_note.note = format!(
-                "{}\n## Transcription\n{}",
-                audio_note.note, audio_note.transcription
+            println!(
+                "{} ({:.1} MB, ~{:.1} min, profile '{}')",
+                audio_note.audio_file_metadata.name,
+                audio_note.audio_file_metadata.size as f64 / 1_048_576.0,
+                estimate.audio_minutes,
+                audio_note.prompt_profile
+            );
+            println!(
+                "  Estimated cost: ${:.3} transcription + ${:.3} analysis",
+                estimate.transcription_cost, estimate.analysis_cost
+            );
+            println!("  Note: {}{}", note_path.display(), remark);
+            planned.insert(note_path);
+        }
+        println!(
+            "Estimated total: ~{:.1} min, ${:.3}",
+            total.audio_minutes,
+            total.total_cost()
+        );
+        let budget = self.config.usage.monthly_budget;
+        if budget > 0.0 {
+            println!(
+                "Spent this month: ${:.2} of the ${:.2} budget",
+                self.ledger.lock().unwrap().cost_this_month(),
+                budget
             );
         }
         Ok(())
     }
 
-    pub async fn save_notes(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for note in &mut self.audio_notes {
-            let _name_without_extension = note.make_note_name_from_title().await;
-            note.make_note_path(&self.config.obsidian_vault_path)
-                .await?;
-
-            // Construct a unique file name for each note to prevent overwrites
-            let file_path = &note.note_path;
-
-            // Asynchronously write the note content to the file
-            tokio::fs::write(file_path, &note.note).await?;
-            println!("Note saved: {}", file_path.display());
+    /// Applies the cache retention policy to the downloaded recordings.
+    pub fn clean_cache(&self) -> Result<(), SecretaryError> {
+        let report = self.cache.clean(&self.ledger.lock().unwrap(), false)?;
+        if report.files > 0 {
+            println!(
+                "Removed {} cached recordings ({:.1} MB)",
+                report.files,
+                report.bytes as f64 / 1_048_576.0
+            );
         }
         Ok(())
     }
@@ -0,0 +1,390 @@
+use chrono::{Datelike, Days, Months, NaiveDate, Weekday};
+use std::ops::Range;
+
+/// Words that may introduce a due date, e.g. "by Friday"; cut along with it.
+const CONNECTORS: [&str; 5] = ["by", "on", "until", "before", "due"];
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// A due date found in a text, and where it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DueDate {
+    pub date: NaiveDate,
+    /// Byte range of the words that gave the date, including "by", "on" and
+    /// the like before them.
+    pub range: Range<usize>,
+}
+
+/// Finds the first due date in `text`, relative to `today`. Understands ISO
+/// dates, "today", "tomorrow", weekdays ("Friday" is the next one, "next
+/// Friday" the one in next week), "next week" (its Monday), "next month" (its
+/// first day), "end of the week" (Friday), "end of the month", "in 3 days",
+/// "in two weeks", and dates like "March 10th" or "by 10 March", which are
+/// taken to be in the coming year if this year's has passed. A month name
+/// only makes a date with an ordinal day or after "by", "on" and the like, so
+/// "we may 2 x the budget" or "ask Jan 5 questions" have none.
+pub fn parse_due_date(text: &str, today: NaiveDate) -> Option<DueDate> {
+    let words = words(text);
+    for start in 0..words.len() {
+        let mut first = start;
+        if first > 0 && words[first - 1].text == "the" {
+            first -= 1;
+        }
+        let introduced = first > 0 && CONNECTORS.contains(&words[first - 1].text.as_str());
+        if introduced {
+            first -= 1;
+        }
+        let Some((date, length)) = date_at(&words[start..], text, today, introduced) else {
+            continue;
+        };
+        return Some(DueDate {
+            date,
+            range: words[first].range.start..words[start + length - 1].range.end,
+        });
+    }
+    None
+}
+
+struct Word {
+    /// Lowercase, without a trailing ordinal suffix ("10th" is "10").
+    text: String,
+    /// Whether the word was a number with an ordinal suffix.
+    ordinal: bool,
+    range: Range<usize>,
+}
+
+fn words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(begin)) => {
+                let word = text[begin..index].to_lowercase();
+                let number = ["st", "nd", "rd", "th"]
+                    .iter()
+                    .find_map(|suffix| word.strip_suffix(suffix))
+                    .filter(|number| {
+                        !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+                    });
+                words.push(Word {
+                    ordinal: number.is_some(),
+                    text: number.map(str::to_string).unwrap_or(word),
+                    range: begin..index,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    words
+}
+
+/// The date the words at the start of `words` give, and how many words that is.
+/// `introduced` tells whether "by", "on" or the like came before them.
+fn date_at(
+    words: &[Word],
+    text: &str,
+    today: NaiveDate,
+    introduced: bool,
+) -> Option<(NaiveDate, usize)> {
+    let word = |index: usize| words.get(index).map(|word| word.text.as_str());
+    // Month names and numbers are common words too
+    let dated = |day: usize| introduced || words[day].ordinal;
+
+    // 2026-03-10, which splits into three words
+    if words.len() >= 3 {
+        let joined = &text[words[0].range.start..words[2].range.end];
+        if let Ok(date) = NaiveDate::parse_from_str(joined, "%Y-%m-%d") {
+            return Some((date, 3));
+        }
+    }
+
+    match (word(0)?, word(1), word(2), word(3)) {
+        ("today" | "tonight", ..) => Some((today, 1)),
+        ("tomorrow", ..) => Some((today.checked_add_days(Days::new(1))?, 1)),
+        ("next", Some("week"), ..) => Some((monday_of(today)?.checked_add_days(Days::new(7))?, 2)),
+        ("next", Some("month"), ..) => {
+            Some((first_of_month(today).checked_add_months(Months::new(1))?, 2))
+        }
+        ("next", Some(name), ..) => {
+            let weekday = weekday(name)?;
+            let days = 7 + u64::from(weekday.num_days_from_monday());
+            Some((monday_of(today)?.checked_add_days(Days::new(days))?, 2))
+        }
+        ("end", Some("of"), Some("the"), Some(period)) => Some((end_of(period, today)?, 4)),
+        ("end", Some("of"), Some(period), _) => Some((end_of(period, today)?, 3)),
+        ("in", Some(count), Some(unit), _) => {
+            // The count comes from model output, so it may be absurdly large
+            let count = number(count)?;
+            let date = match unit.trim_end_matches('s') {
+                "day" => today.checked_add_days(Days::new(count.into()))?,
+                "week" => today.checked_add_days(Days::new(u64::from(count) * 7))?,
+                "month" => today.checked_add_months(Months::new(count))?,
+                _ => return None,
+            };
+            Some((date, 3))
+        }
+        (name, ..) if weekday(name).is_some() => {
+            let weekday = weekday(name)?;
+            let ahead =
+                (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+            let ahead = if ahead == 0 { 7 } else { ahead };
+            Some((today.checked_add_days(Days::new(ahead.into()))?, 1))
+        }
+        (name, Some(day), ..) if month(name).is_some() && dated(1) => {
+            let year = word(2)
+                .filter(|year| year.len() == 4)
+                .and_then(|year| year.parse().ok());
+            let date = calendar_date(month(name)?, day.parse().ok()?, year, today)?;
+            Some((date, if year.is_some() { 3 } else { 2 }))
+        }
+        (day, Some("of"), Some(name), _) if month(name).is_some() && dated(0) => {
+            let year = word(3)
+                .filter(|year| year.len() == 4)
+                .and_then(|year| year.parse().ok());
+            let date = calendar_date(month(name)?, day.parse().ok()?, year, today)?;
+            Some((date, if year.is_some() { 4 } else { 3 }))
+        }
+        (day, Some(name), ..) if month(name).is_some() && dated(0) => {
+            let year = word(2)
+                .filter(|year| year.len() == 4)
+                .and_then(|year| year.parse().ok());
+            let date = calendar_date(month(name)?, day.parse().ok()?, year, today)?;
+            Some((date, if year.is_some() { 3 } else { 2 }))
+        }
+        _ => None,
+    }
+}
+
+fn weekday(name: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(weekday, _)| *weekday == name)
+        .map(|(_, weekday)| *weekday)
+}
+
+/// 1 for January, also for "jan" and "sept".
+fn month(name: &str) -> Option<u32> {
+    MONTHS
+        .iter()
+        .position(|month| {
+            *month == name
+                || (name.len() == 3 && month.starts_with(name))
+                || (name == "sept" && *month == "september")
+        })
+        .map(|index| index as u32 + 1)
+}
+
+fn number(word: &str) -> Option<u32> {
+    const NUMBERS: [&str; 12] = [
+        "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten", "eleven",
+        "twelve",
+    ];
+    match word {
+        "a" | "an" => Some(1),
+        _ => word.parse().ok().or_else(|| {
+            NUMBERS
+                .iter()
+                .position(|number| *number == word)
+                .map(|index| index as u32 + 1)
+        }),
+    }
+}
+
+fn monday_of(day: NaiveDate) -> Option<NaiveDate> {
+    day.checked_sub_days(Days::new(day.weekday().num_days_from_monday().into()))
+}
+
+fn first_of_month(day: NaiveDate) -> NaiveDate {
+    day.with_day(1).unwrap_or(day)
+}
+
+/// The end of "day", "week" (its Friday, or the next one on weekends) or "month".
+fn end_of(period: &str, today: NaiveDate) -> Option<NaiveDate> {
+    match period {
+        "day" => Some(today),
+        "week" => {
+            let friday = monday_of(today)?.checked_add_days(Days::new(4))?;
+            if friday < today {
+                friday.checked_add_days(Days::new(7))
+            } else {
+                Some(friday)
+            }
+        }
+        "month" => first_of_month(today)
+            .checked_add_months(Months::new(1))
+            .and_then(|next| next.pred_opt()),
+        _ => None,
+    }
+}
+
+/// `day` of `month`, in `year` or else the first year from `today` it hasn't passed in.
+fn calendar_date(month: u32, day: u32, year: Option<i32>, today: NaiveDate) -> Option<NaiveDate> {
+    match year {
+        Some(year) => NaiveDate::from_ymd_opt(year, month, day),
+        None => {
+            let date = NaiveDate::from_ymd_opt(today.year(), month, day)?;
+            if date < today {
+                NaiveDate::from_ymd_opt(today.year() + 1, month, day)
+            } else {
+                Some(date)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Monday, 19 October 2026.
+    fn monday() -> NaiveDate {
+        date(2026, 10, 19)
+    }
+
+    fn due(text: &str, today: NaiveDate) -> Option<NaiveDate> {
+        parse_due_date(text, today).map(|due| due.date)
+    }
+
+    #[test]
+    fn weekdays_roll_over_to_the_next_week() {
+        assert_eq!(due("Call by Friday", monday()), Some(date(2026, 10, 23)));
+        assert_eq!(due("Call on Monday", monday()), Some(date(2026, 10, 26)));
+        assert_eq!(
+            due("Call Sunday", date(2026, 10, 24)),
+            Some(date(2026, 10, 25))
+        );
+        assert_eq!(due("Call next Friday", monday()), Some(date(2026, 10, 30)));
+        assert_eq!(
+            due("Call next Monday", date(2026, 10, 25)),
+            Some(date(2026, 10, 26))
+        );
+    }
+
+    #[test]
+    fn relative_days_weeks_and_months() {
+        assert_eq!(due("today", monday()), Some(monday()));
+        assert_eq!(due("tomorrow", monday()), Some(date(2026, 10, 20)));
+        assert_eq!(
+            due("next week", date(2026, 10, 22)),
+            Some(date(2026, 10, 26))
+        );
+        assert_eq!(due("next month", monday()), Some(date(2026, 11, 1)));
+        assert_eq!(due("in 3 days", monday()), Some(date(2026, 10, 22)));
+        assert_eq!(due("in two weeks", monday()), Some(date(2026, 11, 2)));
+        assert_eq!(
+            due("in a month", date(2026, 1, 31)),
+            Some(date(2026, 2, 28))
+        );
+    }
+
+    #[test]
+    fn absurd_counts_are_no_date() {
+        assert_eq!(due("in 100000000 days", monday()), None);
+        assert_eq!(due("in 4000000000 weeks", monday()), None);
+        assert_eq!(due("in 4000000000 months", monday()), None);
+        assert_eq!(due("tomorrow", NaiveDate::MAX), None);
+        assert_eq!(due("Friday", NaiveDate::MAX), None);
+    }
+
+    #[test]
+    fn end_of_the_week_on_weekends_is_next_friday() {
+        assert_eq!(
+            due("end of the week", date(2026, 10, 21)),
+            Some(date(2026, 10, 23))
+        );
+        assert_eq!(
+            due("end of week", date(2026, 10, 23)),
+            Some(date(2026, 10, 23))
+        );
+        assert_eq!(
+            due("end of the week", date(2026, 10, 24)),
+            Some(date(2026, 10, 30))
+        );
+        assert_eq!(
+            due("end of the week", date(2026, 10, 25)),
+            Some(date(2026, 10, 30))
+        );
+    }
+
+    #[test]
+    fn end_of_the_month() {
+        assert_eq!(due("end of the month", monday()), Some(date(2026, 10, 31)));
+        assert_eq!(
+            due("end of month", date(2026, 12, 5)),
+            Some(date(2026, 12, 31))
+        );
+        assert_eq!(
+            due("end of the month", date(2028, 2, 1)),
+            Some(date(2028, 2, 29))
+        );
+    }
+
+    #[test]
+    fn calendar_dates_roll_over_to_next_year() {
+        assert_eq!(due("on March 10th", monday()), Some(date(2027, 3, 10)));
+        assert_eq!(due("by 10 March", monday()), Some(date(2027, 3, 10)));
+        assert_eq!(due("the 1st of Dec", monday()), Some(date(2026, 12, 1)));
+        assert_eq!(due("on October 19", monday()), Some(monday()));
+        assert_eq!(due("before 5 Nov 2027", monday()), Some(date(2027, 11, 5)));
+        assert_eq!(due("by March 10 2026", monday()), Some(date(2026, 3, 10)));
+        assert_eq!(due("by Feb 30", monday()), None);
+    }
+
+    #[test]
+    fn iso_dates() {
+        assert_eq!(due("due 2026-11-03", monday()), Some(date(2026, 11, 3)));
+        assert_eq!(due("due 2026-13-03", monday()), None);
+    }
+
+    #[test]
+    fn range_includes_the_connector() {
+        let text = "Prepare report by the end of the week, please";
+        let found = parse_due_date(text, monday()).unwrap();
+        assert_eq!(&text[found.range], "by the end of the week");
+
+        let text = "Prepare report due 2026-11-03";
+        let found = parse_due_date(text, monday()).unwrap();
+        assert_eq!(&text[found.range], "due 2026-11-03");
+    }
+
+    #[test]
+    fn text_without_a_date() {
+        assert_eq!(due("Review the budget", monday()), None);
+        assert_eq!(due("Anna may review 2 docs", monday()), None);
+        assert_eq!(due("We may 2x the budget", monday()), None);
+        assert_eq!(due("We may 2 x the budget", monday()), None);
+        assert_eq!(due("Ask Jan 5 questions", monday()), None);
+        assert_eq!(due("Order 3 March hares", monday()), None);
+        assert_eq!(due("Mayday drill", monday()), None);
+        assert_eq!(due("", monday()), None);
+    }
+}
@@ -1,7 +1,151 @@
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 pub fn parse_timestamp_to_system_time(timestamp: &str) -> Result<SystemTime, chrono::ParseError> {
     let parsed_date = DateTime::parse_from_rfc3339(timestamp)?;
     Ok(parsed_date.with_timezone(&Utc).into())
 }
+
+/// Longest file name (without extension), in bytes, that `sanitize_file_name`
+/// produces. File systems allow 255 bytes; the rest is left for a numeric
+/// suffix (`Title 2`) and the `.md` extension.
+pub const MAX_FILE_NAME_BYTES: usize = 200;
+
+/// Turns arbitrary text, such as a model-generated title, into a file name that
+/// is valid on every platform and can't escape its folder: path separators and
+/// characters Windows or Obsidian links reject become spaces, runs of whitespace
+/// collapse, and the result is trimmed and cut to `MAX_FILE_NAME_BYTES`.
+pub fn sanitize_file_name(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '#' | '^' | '[' | ']' => ' ',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    let mut end = collapsed.len().min(MAX_FILE_NAME_BYTES);
+    while !collapsed.is_char_boundary(end) {
+        end -= 1;
+    }
+    let truncated = &collapsed[..end];
+    // Leading dots hide files; trailing dots and spaces are dropped by Windows
+    let trimmed = truncated.trim_matches(|c: char| c == '.' || c.is_whitespace());
+
+    if trimmed.is_empty() {
+        return "Untitled".to_string();
+    }
+    let reserved = ["CON", "PRN", "AUX", "NUL"]
+        .iter()
+        .any(|name| trimmed.eq_ignore_ascii_case(name))
+        || (trimmed.len() == 4
+            && trimmed.get(..3).is_some_and(|prefix| {
+                prefix.eq_ignore_ascii_case("COM") || prefix.eq_ignore_ascii_case("LPT")
+            })
+            && trimmed.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        format!("{} note", trimmed)
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Whether `name` matches `pattern`, ignoring case. `*` matches any run of
+/// characters and `?` any single character.
+pub fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    // On a mismatch, let the last `*` swallow one more character and go on
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Every markdown file in `dir` and its subfolders, skipping hidden folders.
+pub fn markdown_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return files;
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("Error reading directory entry: {}", e);
+                continue; // Continue with next entry on error
+            }
+        };
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            files.extend(markdown_files(&path));
+        } else if path.extension().and_then(std::ffi::OsStr::to_str) == Some("md") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_characters_become_spaces() {
+        assert_eq!(sanitize_file_name("Q3 plan: draft/v2?"), "Q3 plan draft v2");
+        assert_eq!(sanitize_file_name("  [[Link]] #tag\n"), "Link tag");
+    }
+
+    #[test]
+    fn long_names_are_cut_on_a_character_boundary() {
+        let name = sanitize_file_name(&"Überprüfung ".repeat(40));
+        assert!(name.len() <= MAX_FILE_NAME_BYTES);
+        assert!(name.starts_with("Überprüfung Überprüfung"));
+
+        let name = sanitize_file_name(&"会議".repeat(100));
+        assert!(name.len() <= MAX_FILE_NAME_BYTES);
+        assert_eq!(name.chars().count(), MAX_FILE_NAME_BYTES / 3);
+    }
+
+    #[test]
+    fn dots_and_spaces_are_trimmed() {
+        assert_eq!(sanitize_file_name("Notes..."), "Notes");
+        assert_eq!(sanitize_file_name(".hidden . "), "hidden");
+        assert_eq!(sanitize_file_name(" ... "), "Untitled");
+        assert_eq!(sanitize_file_name(""), "Untitled");
+    }
+
+    #[test]
+    fn reserved_names_get_a_suffix() {
+        assert_eq!(sanitize_file_name("con"), "con note");
+        assert_eq!(sanitize_file_name("COM1"), "COM1 note");
+        assert_eq!(sanitize_file_name("lpt9."), "lpt9 note");
+        assert_eq!(sanitize_file_name("Console"), "Console");
+        assert_eq!(sanitize_file_name("COMA"), "COMA");
+    }
+}
@@ -1 +1,2 @@
+pub mod date_utils;
 pub mod file_utils;
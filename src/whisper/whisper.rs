@@ -1,51 +1,68 @@
 use crate::config::config::Config;
+use crate::error::error::SecretaryError;
+use crate::http::http::HttpClient;
 use crate::models::audio_note::AudioNote;
-use reqwest::{multipart, Client};
+use bytes::Bytes;
+use reqwest::{multipart, Body};
 use serde_json::Value;
-use std::error::Error;
-use std::fs;
+
+pub const TRANSCRIPTION_MODEL: &str = "whisper-1";
 
 #[derive(Clone)]
 pub struct WhisperClient {
-    client: Client,
+    http: HttpClient,
     api_key: String,
 }
 
 impl WhisperClient {
-    pub fn new(config: &Config) -> Self {
-        let api_key = config.whisper_api_key.clone();
-        let client = Client::new();
-        WhisperClient { client, api_key }
+    pub fn new(config: &Config, http: HttpClient) -> Self {
+        let api_key = config.openai.whisper_api_key.expose().to_string();
+        WhisperClient { http, api_key }
     }
 
-    pub async fn transcribe_file(&self, audio_note: &mut AudioNote) -> Result<(), Box<dyn Error>> {
+    pub async fn transcribe_file(&self, audio_note: &mut AudioNote) -> Result<(), SecretaryError> {
         let file_name = &audio_note.note_name;
 
-        let audio_bytes = fs::read(&audio_note.local_audio_file_path)?;
-
-        let form = multipart::Form::new().text("model", "whisper-1").part(
-            "file",
-            multipart::Part::bytes(audio_bytes).file_name(file_name.clone()),
-        );
+        let audio_bytes = Bytes::from(tokio::fs::read(&audio_note.local_audio_file_path).await?);
 
         let url = "https://api.openai.com/v1/audio/transcriptions";
         let response = self
-            .client
-            .post(url)
-            .bearer_auth(&self.api_key)
-            .multipart(form)
-            .send()
+            .http
+            .send(|| {
+                // A multipart form can't be reused, so every attempt builds its own;
+                // they share the audio rather than copying it
+                let form = multipart::Form::new()
+                    .text("model", TRANSCRIPTION_MODEL)
+                    .text("response_format", "verbose_json")
+                    .part(
+                        "file",
+                        multipart::Part::stream(Body::from(audio_bytes.clone()))
+                            .file_name(file_name.clone()),
+                    );
+                self.http
+                    .client
+                    .post(url)
+                    .bearer_auth(&self.api_key)
+                    .multipart(form)
+            })
             .await?;
+        if !response.status().is_success() {
+            return Err(SecretaryError::from_response("OpenAI", response)
+                .await
+                .for_key("openai.whisper_api_key"));
+        }
 
         let response_json = response.json::<Value>().await?;
 
         if let Some(text) = response_json["text"].as_str() {
             audio_note.transcription = text.to_string();
+            audio_note.language = response_json["language"].as_str().map(str::to_string);
+            audio_note.duration_seconds = response_json["duration"].as_f64();
             Ok(())
-        } else if let Some(error) = response_json["error"]["message"].as_str() {
-            Err(format!("Error during transcription: {}", error).into())
         } else {
-            Err("Unexpected response format".into())
+            Err(SecretaryError::Decode(
+                "Whisper response has no transcription text".to_string(),
+            ))
         }
     }
 }